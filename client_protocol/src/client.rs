@@ -7,267 +7,1268 @@
  */
 use std::{
     collections::{HashMap, VecDeque},
-    sync::{Arc, mpsc},
-    net::SocketAddr,
-    io::stdin,
+    sync::{Arc, atomic::{AtomicU64, Ordering}},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    io::{self, stdin},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 use tokio::{
     net::UdpSocket,
-    time::{self, Duration},
-    sync::Mutex,
+    time::{self, Duration, Instant},
+    sync::{mpsc, oneshot, Mutex, Semaphore},
+};
+use ed25519_dalek::SigningKey;
+use protocol::{
+    Deregistration, ErrorCode, Heartbeat, LookupByName, PeerStatus, Query, 
+    Registration, Request, RequestBody, Response, ResponseBody, 
+    Watch, DEFAULT_SERVER_PORT, PEER_CACHE_TTL_SECS,
 };
-use json::{JsonValue, stringify, parse};
 use uuid::Uuid;
-use crate::message::{Message, MessageError};
+use x25519_dalek::PublicKey;
+use protocol::codec::{Codec, SUPPORTED_CODECS};
+use protocol::crypto::{self, KeyPair};
+use protocol::fragment::{self, Reassembler};
+use protocol::group::Group;
+use protocol::message::{Message, MessageType};
+use protocol::net::{bind_udp, reachable_from};
+use protocol::{punch, transfer};
+use crate::contacts::ContactBook;
+use crate::demux::{Datagram, PendingResponse};
+use crate::identity::Identity;
+use crate::transfer::FileReceiver;
+
+// how long a sender keeps retransmitting before giving up on a message
+static DEFAULT_DELIVERY_DEADLINE: Duration = Duration::from_secs(5);
+// time waited for an ack before the first retransmission. Doubles every retry
+static INITIAL_RETRANSMIT_INTERVAL: Duration = Duration::from_millis(200);
+// number of received `msg_id`s remembered for duplicate detection
+static SEEN_MSG_IDS_CAPACITY: usize = 1024;
+// number of sent messages whose delivery status is remembered
+static DELIVERIES_CAPACITY: usize = 1024;
+// received messages checked and handled at once. Checking the sender's key
+// may take a lookup, so more would only pile up behind the server
+static MAX_MESSAGE_TASKS: usize = 64;
+// how long a sender the server doesn't know is refused without asking again
+static UNKNOWN_SENDER_TTL: Duration = Duration::from_secs(10);
+// number of unknown senders remembered
+static UNKNOWN_SENDERS_CAPACITY: usize = 1024;
+// groups a peer may tell us about. Nobody has to accept a group before its
+// messages arrive, so a peer could otherwise make up groups without end
+static MAX_GROUPS_PER_PEER: usize = 32;
+// largest encoded message sent or accepted, unless configured otherwise
+static DEFAULT_MAX_MESSAGE_SIZE: usize = 256 * 1024;
+// how long the fragments of a message may take to arrive. Senders retransmit
+// the whole message until their delivery deadline, so no point waiting longer
+static REASSEMBLY_TIMEOUT: Duration = DEFAULT_DELIVERY_DEADLINE;
+// where received files are saved, unless configured otherwise
+static DEFAULT_DOWNLOADS_DIR: &str = "downloads";
+// lease requested from the server upon registration
+static DEFAULT_LEASE_TTL: Duration = Duration::from_secs(60);
+// how long a message is sent straight to the peer before falling back to the
+// server's relay
+static DIRECT_DELIVERY_DEADLINE: Duration = Duration::from_secs(2);
+// how long a looked up peer is reached at the same address before the server
+// is asked again. It tells us about moves in the meantime
+static PEER_CACHE_TTL: Duration = Duration::from_secs(PEER_CACHE_TTL_SECS);
+// time waited for the server's response before the request is sent again,
+// unless configured otherwise. Doubles every retry
+static DEFAULT_SERVER_TIMEOUT: Duration = Duration::from_millis(500);
+// times a request is sent again before the server is given up on
+static DEFAULT_SERVER_RETRIES: u32 = 3;
+
+/// delivery state of a message sent with `send_message`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    Relayed,        // delivered through the server's relay
+    Deposited,      // left in the mailbox of an offline peer
+    Failed,
+}
+
+impl DeliveryStatus {
+    /// whether the message reached the peer, whichever way
+    pub fn is_delivered(&self) -> bool {
+        matches!(self, DeliveryStatus::Delivered | DeliveryStatus::Relayed)
+    }
+}
+
+/// delivery states of the most recently sent messages, by `msg_id`. The
+/// oldest are forgotten to make room for new ones, so that a long running
+/// client doesn't grow without bound
+#[derive(Debug, Default)]
+pub struct Deliveries {
+    statuses: HashMap<Uuid, DeliveryStatus>,
+    order: VecDeque<Uuid>,      // ids of the tracked messages, oldest first
+}
+
+impl Deliveries {
+    /// the delivery status of the message `msg_id`, if it is still tracked
+    pub fn get(&self, msg_id: &Uuid) -> Option<DeliveryStatus> {
+        self.statuses.get(msg_id).copied()
+    }
+
+    /// records the delivery status of the message `msg_id`
+    pub fn insert(&mut self, msg_id: Uuid, status: DeliveryStatus) {
+        if self.statuses.insert(msg_id, status).is_some() {
+            return;
+        }
+        self.order.push_back(msg_id);
+        while self.order.len() > DELIVERIES_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.statuses.remove(&oldest);
+            }
+        }
+    }
+}
 
-// the host port. Change to IP addr in future.
-static HOST_PORT: u16 = 50_000;
-// client UUID is NULL upon creation. Changed upon registration.
-static NULL_UUID_STR: &str = "00000000-0000-0000-0000-000000000000";
+/// how a datagram travels between us and a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Route {
+    Direct(SocketAddr),
+    Relay(Uuid),        // through the server, to or from the peer `uuid`
+}
+
+/// hands the ack of a message, and the route it came by, over from 
+/// `incoming_traff_loop` to the task delivering the message. Only an ack
+/// from the peer the message was encrypted for is handed over
+pub struct PendingAck {
+    pub peer_uuid: Uuid,
+    pub public_key: PublicKey,
+    pub ack_sender: oneshot::Sender<(Message, Route)>,
+}
+
+/// held while a peer is looked up, so that concurrent sends to the peer wait
+/// for that lookup instead of asking the server again
+pub type PeerLookup = Arc<Mutex<()>>;
+
+/// what a client knows about another peer, as obtained from the server
+#[derive(Debug, Clone, Copy)]
+pub struct PeerEntry {
+    pub addr: SocketAddr,
+    pub observed_addr: Option<SocketAddr>,  // where the server sees the peer
+    pub relayed: bool,          // only reachable through the relay so far
+    pub public_key: PublicKey,
+    pub codec: Codec,           // negotiated from the codecs the peer supports
+    pub resolved_at: Instant,   // when the server last told us about it
+}
+
+impl PeerEntry {
+    /// the addresses the peer may be reached at. The one it registered, and
+    /// the one the server sees it at if that differs, i.e. if the peer or the
+    /// server is behind a NAT
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        let mut addrs = vec![self.addr];
+        if let Some(observed_addr) = self.observed_addr {
+            if observed_addr != self.addr {
+                addrs.push(observed_addr);
+            }
+        }
+        addrs
+    }
+
+    /// whether the entry is too old to be trusted without asking the server
+    pub fn is_stale(&self) -> bool {
+        self.resolved_at.elapsed() >= PEER_CACHE_TTL
+    }
+}
 
 /// Client in the p2p network
 #[derive(Clone)]
 pub struct Client {
-    pub listening_socket: Arc<Mutex<UdpSocket>>,
-    pub peer_map: Arc<Mutex<HashMap<Uuid, SocketAddr>>>,
+    pub listening_socket: Arc<UdpSocket>,
+    pub listen_addr: SocketAddr,
+    pub server_addr: SocketAddr,
+    pub pending_acks: Arc<Mutex<HashMap<Uuid, PendingAck>>>,
+    pub pending_requests: Arc<Mutex<HashMap<Uuid, PendingResponse>>>,
+    pub peer_map: Arc<Mutex<HashMap<Uuid, PeerEntry>>>,
+    pub lookups: Arc<Mutex<HashMap<Uuid, PeerLookup>>>,
     pub recv_queue: Arc<Mutex<VecDeque<Message>>>,
+    pub deliveries: Arc<Mutex<Deliveries>>,
+    pub seen_msg_ids: Arc<Mutex<VecDeque<Uuid>>>,
+    pub message_tasks: Arc<Semaphore>,
+    // senders the server didn't know, and until when they are refused
+    pub unknown_senders: Arc<Mutex<HashMap<Uuid, Instant>>>,
+    pub delivery_deadline: Duration,
+    pub max_message_size: usize,
+    pub lease_ttl: Duration,
+    pub server_timeout: Duration,   // before the first retransmission
+    pub server_retries: u32,
+    pub codecs: Vec<Codec>,
+    pub username: Option<String>,
+    pub file_receiver: Arc<FileReceiver>,
+    pub groups: Arc<Mutex<HashMap<Uuid, Group>>>,
+    // number of groups learned from each peer
+    pub groups_learned: Arc<Mutex<HashMap<Uuid, usize>>>,
+    pub contacts: Arc<Mutex<ContactBook>>,
+    pub keys: Arc<KeyPair>,
+    pub identity: Arc<SigningKey>,
     pub uuid: Uuid,
 }
 
 /// protocol implementations
 impl Client {
-    /// build a new Client
+    /// build a new Client with freshly generated keys. Its UUID is nil until
+    /// it registers with the server.
     ///
-    /// `port`: the port that the client will listen on
-    pub async fn build(port: u16) -> Result<Client, ClientError> {
-        let addr = SocketAddr::from(([127, 0, 0, 1], port));
-        
-        // attempt to bind UDP socket
-        let listening_socket = match UdpSocket::bind(addr).await {
+    /// `listen_addr`: the interface and port that the client will listen on
+    pub async fn build(listen_addr: SocketAddr) -> Result<Client, ClientError> {
+        Client::build_with_identity(listen_addr, Identity::generate()).await
+    }
+
+    /// build a new Client that re-uses a persisted identity, so that it 
+    /// registers under the same UUID as in previous runs
+    ///
+    /// `listen_addr`: the interface and port that the client will listen on,
+    /// e.g. `0.0.0.0:6001` for every interface
+    /// `identity`: the UUID and keys of the client
+    pub async fn build_with_identity(listen_addr: SocketAddr, 
+                                     identity: Identity) 
+        -> Result<Client, ClientError> {
+        // attempt to bind UDP socket, dual-stack when bound to `::`
+        let listening_socket = match bind_udp(listen_addr)
+            .and_then(UdpSocket::from_std) {
             Ok(socket) => socket,
             Err(err) => {
                 let err_msg = format!(
-                    "problem creating client: {err}");
+                    "problem creating client on {listen_addr}: {err}");
                 return Err(ClientError::ClientCreationError(err_msg));
             }
         };
 
-        let peer_map: HashMap<Uuid, SocketAddr> = HashMap::new();
+        let peer_map: HashMap<Uuid, PeerEntry> = HashMap::new();
         let recv_queue: Arc<Mutex<VecDeque<Message>>> =
             Arc::new(Mutex::new(VecDeque::new()));
+        let listen_addr = listening_socket.local_addr().unwrap();
         Ok(Client{ 
-            listening_socket: Arc::new(Mutex::new(listening_socket)), 
+            listening_socket: Arc::new(listening_socket), 
+            listen_addr,
+            server_addr: SocketAddr::from(([127, 0, 0, 1], 
+                                           DEFAULT_SERVER_PORT)),
+            pending_acks: Arc::new(Mutex::new(HashMap::new())),
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
             peer_map: Arc::new(Mutex::new(peer_map)), 
+            lookups: Arc::new(Mutex::new(HashMap::new())),
             recv_queue,
-            uuid: NULL_UUID_STR.to_string().parse().unwrap()
+            deliveries: Arc::new(Mutex::new(Deliveries::default())),
+            seen_msg_ids: Arc::new(Mutex::new(VecDeque::new())),
+            message_tasks: Arc::new(Semaphore::new(MAX_MESSAGE_TASKS)),
+            unknown_senders: Arc::new(Mutex::new(HashMap::new())),
+            delivery_deadline: DEFAULT_DELIVERY_DEADLINE,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            lease_ttl: DEFAULT_LEASE_TTL,
+            server_timeout: DEFAULT_SERVER_TIMEOUT,
+            server_retries: DEFAULT_SERVER_RETRIES,
+            codecs: SUPPORTED_CODECS.to_vec(),
+            username: None,
+            file_receiver: Arc::new(FileReceiver::new(
+                    identity.uuid, PathBuf::from(DEFAULT_DOWNLOADS_DIR))),
+            groups: Arc::new(Mutex::new(HashMap::new())),
+            groups_learned: Arc::new(Mutex::new(HashMap::new())),
+            contacts: Arc::new(Mutex::new(ContactBook::default())),
+            keys: Arc::new(identity.keys),
+            identity: Arc::new(identity.signing_key),
+            uuid: identity.uuid,
         })
     }
 
+    /// persists the client's UUID and keys so that the next run can register
+    /// under the same UUID
+    ///
+    /// `path`: the identity file
+    pub fn save_identity(&self, path: &Path) -> Result<(), ClientError> {
+        let identity = Identity {
+            uuid: self.uuid,
+            signing_key: (*self.identity).clone(),
+            keys: (*self.keys).clone(),
+        };
+        identity.save(path)
+    }
+
+    /// returns the delivery status of a message previously sent by this
+    /// client, unless it was sent so long ago that it was forgotten
+    ///
+    /// `msg_id`: the id of the sent message
+    pub async fn delivery_status(&self, msg_id: &Uuid) 
+        -> Option<DeliveryStatus> {
+        self.deliveries.lock().await.get(msg_id)
+    }
+
     /// sends a message to recipient with known UUID.
     ///
     /// `self`'s `peer_map` can be modified in the event that a server lookup
     /// takes place for an unknown `(peer_uid, addr)` mapping.
     ///
+    /// Returns `Delivered` or `Relayed` if the recipient acknowledged the
+    /// message before `delivery_deadline`, straight or through the server's
+    /// relay, `Deposited` if the recipient is offline and the message was
+    /// left in its mailbox, or `Failed` if no ack came. Errors if the message
+    /// can't be created, the recipient can't be looked up, or the deposit is
+    /// refused.
+    ///
     /// `peer_uuid`: the uuid of the recipient
     /// `msg`: the message data
     async fn send_message(&mut self, peer_uuid: &Uuid, msg_data: &str) 
-        -> Result<(), ClientError> {
-        
-        let mut peer_map = self.peer_map.lock().await;
+        -> Result<DeliveryStatus, ClientError> {
+        let msg = self.new_message(peer_uuid, msg_data)?;
+        self.send_tracked(&msg).await
+    }
 
-        // check if the (uuid <-> addr) is cached. Otherwise retrieve from CIS 
-        if !peer_map.contains_key(peer_uuid) {
-            match self.server_lookup_uuid(peer_uuid).await {
-                Ok(socket_addr) => { 
-                    peer_map.insert(*peer_uuid, socket_addr); 
-                },
-                Err(err) => 
-                    return Err(err),
+    /// creates a data message from this client to a peer
+    ///
+    /// `peer_uuid`: the uuid of the recipient
+    /// `msg_data`: the message data
+    pub(crate) fn new_message(&self, peer_uuid: &Uuid, msg_data: &str) 
+        -> Result<Message, ClientError> {
+        match Message::new(*peer_uuid, self.uuid, msg_data) {
+            Ok(msg) => Ok(msg),
+            Err(err) => {
+                let err_msg = format!("Could not create message. {}", err);
+                Err(ClientError::UdpFailureError(err_msg))
             }
         }
+    }
 
-        // address will be cached now
-        let addr = peer_map.get(peer_uuid).unwrap();
-
-        // format msg as JSON
-        let mut msg_json = JsonValue::new_object();
-        msg_json["src_uuid"] = JsonValue::from(self.uuid.to_string());
-        msg_json["dst_uuid"] = JsonValue::from(peer_uuid.to_string());
-        msg_json["data"] = JsonValue::from(msg_data.to_string());
-        msg_json["creation_time"] = JsonValue::from(0.to_string());
+    /// sends a message to its `dst_uuid`, keeping track of its delivery in
+    /// `deliveries`
+    ///
+    /// `msg`: the message to send
+    pub(crate) async fn send_tracked(&self, msg: &Message) 
+        -> Result<DeliveryStatus, ClientError> {
+        let peer_entry = match self.resolve_peer(&msg.dst_uuid).await {
+            Ok(peer_entry) => peer_entry,
+            // an offline peer gets the message once it registers again
+            Err(ClientError::PeerOfflineError(_)) => 
+                return self.deposit_tracked(msg).await,
+            Err(err) => return Err(err),
+        };
 
-        // bind socket and send message to recipient. 
-        let out_sock = match UdpSocket::bind("0.0.0.0:0").await {
-            Ok(socket) => socket,
+        self.deliveries.lock().await
+            .insert(msg.msg_id, DeliveryStatus::Pending);
+        // deliver() goes by the cached entry, which may be newer
+        let tried_entry = self.cached_peer(&msg.dst_uuid).await
+            .unwrap_or(peer_entry);
+        let mut acked = self.deliver(&peer_entry, msg).await;
+        // the peer may have moved since we looked it up, e.g. restarted on
+        // another port, in which case it gets another try at its new address
+        if let Ok(None) = acked {
+            match self.re_resolve(&msg.dst_uuid, &tried_entry).await {
+                Ok(Some(peer_entry)) => 
+                    acked = self.deliver(&peer_entry, msg).await,
+                Ok(None) => (),
+                Err(ClientError::PeerOfflineError(_)) => 
+                    return self.deposit_tracked(msg).await,
+                Err(err) => println!("Error looking {} up again: {}", 
+                                     msg.dst_uuid, err),
+            }
+        }
+        let status = match acked {
+            Ok(Some((_, Route::Direct(_)))) => DeliveryStatus::Delivered,
+            Ok(Some((_, Route::Relay(_)))) => DeliveryStatus::Relayed,
+            Ok(None) => DeliveryStatus::Failed,
             Err(err) => {
-                let err_msg = format!("Could not bind UDP socket. {}", err);
-                return Err(ClientError::UdpFailureError(err_msg))
+                self.deliveries.lock().await
+                    .insert(msg.msg_id, DeliveryStatus::Failed);
+                return Err(err);
             }
         };
-        match out_sock.send_to(msg_json.dump().as_bytes(), addr).await {
-            Ok(_) => Ok(()),
-            Err(err) => { 
-                let err_msg = format!("Could not send message to recipient: {}",
-                                      err);
-                Err(ClientError::UdpFailureError(err_msg))
+        self.deliveries.lock().await.insert(msg.msg_id, status);
+        Ok(status)
+    }
+
+    /// leaves a message for an offline peer, keeping track of it in
+    /// `deliveries` like delivered ones
+    ///
+    /// `msg`: the message to leave
+    async fn deposit_tracked(&self, msg: &Message) 
+        -> Result<DeliveryStatus, ClientError> {
+        self.deposit_message(msg).await?;
+        self.deliveries.lock().await
+            .insert(msg.msg_id, DeliveryStatus::Deposited);
+        Ok(DeliveryStatus::Deposited)
+    }
+
+    /// returns what is known about a peer, asking the server if it isn't
+    /// cached in `peer_map` yet or the cached entry is stale. A stale entry
+    /// is still used if the server can't be reached
+    ///
+    /// `peer_uuid`: the uuid of the peer
+    pub async fn resolve_peer(&self, peer_uuid: &Uuid) 
+        -> Result<PeerEntry, ClientError> {
+        // check if the (uuid <-> addr) is cached. Otherwise retrieve from CIS 
+        if let Some(peer_entry) = self.cached_peer(peer_uuid).await {
+            if !peer_entry.is_stale() {
+                return Ok(peer_entry);
             }
         }
+
+        // one lookup per peer at a time. The peer map itself is only locked
+        // briefly, so sends to other peers and the incoming loop go on
+        let lookup = Arc::clone(self.lookups.lock().await.entry(*peer_uuid)
+                                .or_default());
+        let resolved = {
+            let _lookup_guard = lookup.lock().await;
+            self.lookup_peer(peer_uuid).await
+        };
+
+        // the last one done with the lookup removes it, so that `lookups`
+        // doesn't keep an entry for every peer ever looked up. Others only
+        // get hold of it with `lookups` locked, so the count can't go up
+        let mut lookups = self.lookups.lock().await;
+        if Arc::strong_count(&lookup) == 2 {
+            lookups.remove(peer_uuid);
+        }
+        resolved
     }
 
-    /// Registers a client with the server. Done upon initialization.
-    /// Sets UUID in client object, hence the &mut
-    pub async fn register_with_server(&mut self) -> Result<Uuid, ClientError> {
-        // bind arbitrary socket
-        let out_socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-        let mut request = JsonValue::new_object();
+    /// asks the server about a peer, unless it was resolved by whoever held
+    /// its lookup before us. Only called with the peer's lookup held
+    ///
+    /// `peer_uuid`: the uuid of the peer
+    async fn lookup_peer(&self, peer_uuid: &Uuid) 
+        -> Result<PeerEntry, ClientError> {
+        // whoever held the lookup before us may have resolved the peer
+        let cached_entry = self.cached_peer(peer_uuid).await;
+        if let Some(peer_entry) = cached_entry {
+            if !peer_entry.is_stale() {
+                return Ok(peer_entry);
+            }
+        }
 
+        let peer_entry = match self.server_lookup_uuid(peer_uuid).await {
+            Ok(peer_entry) => peer_entry,
+            Err(ClientError::ServerUnavailableError(_) 
+                | ClientError::ServerTimeoutError(_)) 
+                if cached_entry.is_some() => return Ok(cached_entry.unwrap()),
+            Err(err) => {
+                self.peer_map.lock().await.remove(peer_uuid);
+                return Err(err);
+            }
+        };
+        self.punch_if_needed(peer_uuid, &peer_entry).await;
+        self.peer_map.lock().await.insert(*peer_uuid, peer_entry); 
+        Ok(peer_entry)
+    }
 
-        request["req_type"] = JsonValue::from("registration".to_string());
+    /// what `peer_map` holds about a peer, stale or not
+    async fn cached_peer(&self, peer_uuid: &Uuid) -> Option<PeerEntry> {
+        self.peer_map.lock().await.get(peer_uuid).copied()
+    }
 
-        let socket_locked = self.listening_socket.lock().await;
-        request["addr"] = JsonValue::from(socket_locked.local_addr().unwrap()
-                                          .to_string());
+    /// asks the server about a peer again after a message to it went
+    /// unacknowledged. Returns the new entry if the peer is to be reached at
+    /// other addresses now, `None` if they are the ones that just failed
+    ///
+    /// `peer_uuid`: the uuid of the peer
+    /// `tried_entry`: the entry the message was sent by
+    async fn re_resolve(&self, peer_uuid: &Uuid, tried_entry: &PeerEntry) 
+        -> Result<Option<PeerEntry>, ClientError> {
+        self.peer_map.lock().await.remove(peer_uuid);
+        let peer_entry = self.resolve_peer(peer_uuid).await?;
+        if peer_entry.addrs() == tried_entry.addrs() {
+            return Ok(None);
+        }
+        println!("Looked {} up again, now at {}", peer_uuid, peer_entry.addr);
+        Ok(Some(peer_entry))
+    }
 
-        let host_addr = SocketAddr::from(([127, 0, 0, 1], HOST_PORT));
+    /// takes in a move of a peer that the server pushed to us. Its new 
+    /// addresses are used right away, and the relay is only fallen back on
+    /// again if they can't be reached either
+    ///
+    /// `peer_uuid`: the peer that moved
+    /// `addr`: the address it registered
+    /// `observed_addr`: where the server sees it
+    pub(crate) async fn peer_moved(&self, peer_uuid: &Uuid, addr: SocketAddr,
+                                   observed_addr: Option<SocketAddr>) {
+        let peer_entry = match self.peer_map.lock().await.get_mut(peer_uuid) {
+            Some(peer_entry) => {
+                peer_entry.addr = addr;
+                peer_entry.observed_addr = observed_addr;
+                peer_entry.relayed = false;
+                peer_entry.resolved_at = Instant::now();
+                *peer_entry
+            },
+            // forgotten since we looked it up
+            None => return,
+        };
+        println!("{} moved to {}", peer_uuid, addr);
+        self.punch_if_needed(peer_uuid, &peer_entry).await;
+    }
 
-        match out_socket.send_to(request.dump().as_bytes(), host_addr).await {
-            Ok(_) => {},
-            Err(_) => 
-                return Err(
-                    ClientError::ServerUnavailableError("server unavailable"
-                                                        .to_string()))
+    /// the watch sent along with our lookups, so that the server tells us
+    /// when the peer moves. `None` until we are registered
+    ///
+    /// `looked_up`: the UUID or username looked up
+    fn watch(&self, looked_up: &str) -> Option<Watch> {
+        if self.uuid.is_nil() {
+            return None;
         }
+        Some(Watch::new(self.uuid, looked_up, request_timestamp(), 
+                        &self.identity))
+    }
 
-        // wait for server response
-        let mut buf = [0u8; 2014];
-        let (size, _) = match out_socket.recv_from(&mut buf).await {
-            Ok((len, addr)) => (len, addr),
-            Err(err) => {
-                let err_msg = format!(
-                    "Error waiting for server response. {}", err);
-                return Err(ClientError::ServerUnavailableError(err_msg));
+    /// sends `msg` to a peer and waits for its ack. The message is encrypted
+    /// for the peer's public key, encoded with the codec negotiated with the
+    /// peer, and retransmitted with exponential backoff until the ack arrives
+    /// or `delivery_deadline` passes.
+    ///
+    /// The message goes out of the listening socket, through which any hole
+    /// punched in our NAT leads, to every address the peer may be reached
+    /// at. Whichever address the ack comes from is used from then on. If no
+    /// ack arrives within `DIRECT_DELIVERY_DEADLINE`, the message is sent
+    /// through the server's relay instead, and so are the following ones
+    /// once the relay got it through.
+    ///
+    /// Returns the ack, which may carry the peer's reply, and the route it
+    /// came by, or `None` if the message was not acknowledged in time.
+    ///
+    /// `peer_entry`: the recipient
+    /// `msg`: the message to send
+    pub async fn deliver(&self, peer_entry: &PeerEntry, msg: &Message) 
+        -> Result<Option<(Message, Route)>, ClientError> {
+        let msg_bytes = msg.encode(peer_entry.codec, &self.keys, 
+                                   &peer_entry.public_key);
+
+        // messages that don't fit in one datagram go out in fragments
+        let datagrams = match fragment::fragment(&msg_bytes) {
+            Some(datagrams) if msg_bytes.len() <= self.max_message_size => 
+                datagrams,
+            _ => {
+                let err_msg = format!("Message is {}B, the limit is {}B", 
+                                      msg_bytes.len(), self.max_message_size);
+                return Err(ClientError::MessageTooLargeError(err_msg));
+            }
+        };
+
+        // acks arrive on the listening socket, incoming_traff_loop() hands
+        // them over
+        // the caller's entry may predate what earlier deliveries learned
+        let peer_entry = self.cached_peer(&msg.dst_uuid).await
+            .unwrap_or(*peer_entry);
+
+        let (ack_sender, mut ack_receiver) = oneshot::channel();
+        let pending_ack = PendingAck {
+            peer_uuid: msg.dst_uuid,
+            public_key: peer_entry.public_key,
+            ack_sender,
+        };
+        self.pending_acks.lock().await.insert(msg.msg_id, pending_ack);
+        let acked = self.transmit(&peer_entry, &msg.dst_uuid, &datagrams, 
+                                  &mut ack_receiver).await;
+        self.pending_acks.lock().await.remove(&msg.msg_id);
+
+        let (ack, route) = match acked? {
+            Some(acked) => acked,
+            None => return Ok(None),
+        };
+        if let Some(peer_entry) = self.peer_map.lock().await
+            .get_mut(&msg.dst_uuid) {
+            match route {
+                Route::Direct(addr) => {
+                    if addr != peer_entry.addr {
+                        println!("Reaching {} at {} from now on", 
+                                 msg.dst_uuid, addr);
+                        peer_entry.addr = addr;
+                    }
+                    peer_entry.relayed = false;
+                },
+                Route::Relay(_) if !peer_entry.relayed => {
+                    println!("Reaching {} through the relay from now on",
+                             msg.dst_uuid);
+                    peer_entry.relayed = true;
+                },
+                Route::Relay(_) => (),
             }
+        }
+        Ok(Some((ack, route)))
+    }
+
+    /// sends the datagrams of a message to a peer until its ack arrives
+    /// through `ack_receiver`. They go to every address of the peer until
+    /// `DIRECT_DELIVERY_DEADLINE`, or right away if the peer is `relayed`,
+    /// and through the relay from then on. Returns `None` once 
+    /// `delivery_deadline` passes
+    ///
+    /// `peer_entry`: the recipient
+    /// `peer_uuid`: the uuid of the recipient
+    /// `datagrams`: the encoded message
+    /// `ack_receiver`: where the ack is handed over
+    async fn transmit(&self, peer_entry: &PeerEntry, peer_uuid: &Uuid,
+                      datagrams: &[Vec<u8>],
+                      ack_receiver: &mut oneshot::Receiver<(Message, Route)>)
+        -> Result<Option<(Message, Route)>, ClientError> {
+        let deadline = Instant::now() + self.delivery_deadline;
+        // only the peer's addresses of a family our socket speaks
+        let direct_routes: Vec<Route> = peer_entry.addrs().into_iter()
+            .filter(|addr| reachable_from(&self.listen_addr, *addr).is_some())
+            .map(Route::Direct)
+            .collect();
+        let direct_deadline = match peer_entry.relayed 
+            || direct_routes.is_empty() {
+            true => Instant::now(),
+            false => deadline.min(Instant::now() + DIRECT_DELIVERY_DEADLINE),
         };
+        let relay_routes = [Route::Relay(*peer_uuid)];
+
+        let mut relaying = false;
+        let mut retransmit_interval = INITIAL_RETRANSMIT_INTERVAL;
+        loop {
+            // the relay gets a fresh round of retransmissions
+            if !relaying && Instant::now() >= direct_deadline {
+                relaying = true;
+                retransmit_interval = INITIAL_RETRANSMIT_INTERVAL;
+            }
+            let (routes, phase_deadline) = match relaying {
+                true => (&relay_routes[..], deadline),
+                false => (&direct_routes[..], direct_deadline),
+            };
+
+            // a route that fails doesn't matter as long as another one works
+            let mut send_err = None;
+            let mut failed_routes = 0;
+            for route in routes {
+                for datagram in datagrams {
+                    if let Err(err) = self.send_datagram(*route, datagram)
+                        .await {
+                        send_err = Some(err);
+                        failed_routes += 1;
+                        break;
+                    }
+                }
+            }
+            if let Some(err) = send_err {
+                if failed_routes == routes.len() {
+                    return Err(err);
+                }
+            }
 
-        let server_resp = 
-            String::from_utf8(buf[..size].to_vec()).unwrap();
-        let server_resp = json::parse(&server_resp).unwrap();
+            let wait_until = phase_deadline
+                .min(Instant::now() + retransmit_interval);
+            match time::timeout_at(wait_until, &mut *ack_receiver).await {
+                Ok(Ok(acked)) => return Ok(Some(acked)),
+                // the sender is never dropped while we wait
+                Ok(Err(_)) => return Ok(None),
+                Err(_) => (),
+            }
 
-        let client_uuid = &server_resp["uuid"].to_string();
-        let status = &server_resp["status"].to_string();
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            retransmit_interval *= 2;
+        }
+    }
 
-        if status != "OK" {
-            // TODO change this error type
-            return Err(ClientError::ServerUnavailableError(
-                    "Error registering with server".to_string()));
+    /// sends a datagram to a peer from the listening socket, either straight
+    /// to one of its addresses or through the server's relay
+    ///
+    /// `route`: how the datagram gets to the peer
+    /// `datagram`: the datagram to send
+    pub(crate) async fn send_datagram(&self, route: Route, datagram: &[u8])
+        -> Result<(), ClientError> {
+        match route {
+            Route::Direct(addr) => 
+                match self.send_from_listener(datagram, addr).await {
+                    Ok(_) => Ok(()),
+                    Err(err) => {
+                        let err_msg = format!(
+                            "Could not send message to recipient: {}", err);
+                        Err(ClientError::UdpFailureError(err_msg))
+                    }
+                },
+            Route::Relay(peer_uuid) => 
+                self.send_relayed(&peer_uuid, datagram).await,
         }
+    }
+
+    /// sends a datagram from the listening socket, which only reaches the
+    /// addresses of its own family, or both if it is dual-stack
+    ///
+    /// `addr`: where to send the datagram
+    pub(crate) async fn send_from_listener(&self, datagram: &[u8], 
+                                           addr: SocketAddr) 
+        -> io::Result<usize> {
+        match reachable_from(&self.listen_addr, addr) {
+            Some(addr) => self.listening_socket.send_to(datagram, addr).await,
+            None => Err(io::Error::new(io::ErrorKind::AddrNotAvailable,
+                        format!("{} can't reach {}", self.listen_addr, addr))),
+        }
+    }
+
+    /// Registers a client with the server. Done upon initialization.
+    /// Sets UUID in client object, hence the &mut
+    ///
+    /// The request is signed with the client's identity key, from which the
+    /// server derives the UUID. Only the holder of that key can later rebind
+    /// the UUID to another address. The server grants a lease that has to be
+    /// renewed by `heartbeat_loop()`.
+    ///
+    /// A client that already has a UUID asks to be re-registered under it,
+    /// and the server only updates the address it maps to. A client with a
+    /// `username` claims it, which fails if another peer holds it.
+    pub async fn register_with_server(&mut self) -> Result<Uuid, ClientError> {
+        let addr = self.advertised_addr().await?.to_string();
+        let public_key = self.keys.public_key_hex();
+        let ttl = DEFAULT_LEASE_TTL.as_secs();
+        let timestamp = request_timestamp();
+        let payload = Registration::signing_payload(&addr, &public_key, ttl, 
+                                                    timestamp, 
+                                                    self.username.as_deref());
+
+        let request = RequestBody::Registration(Registration {
+            addr,
+            public_key,
+            verifying_key: crypto::verifying_key_hex(&self.identity),
+            ttl,
+            timestamp,
+            signature: crypto::sign(&self.identity, &payload),
+            uuid: if self.uuid.is_nil() { None } else { Some(self.uuid) },
+            codecs: self.codecs.clone(),
+            username: self.username.clone(),
+        });
+
+        let (client_uuid, ttl) = match self.server_request(request).await? {
+            ResponseBody::Registered { uuid, ttl } => (uuid, ttl),
+            other => return Err(unexpected_response(other)),
+        };
 
-        // assumes that the server sends a valid uuid
-        let client_uuid = client_uuid.parse::<Uuid>().unwrap();
+        if !self.uuid.is_nil() && client_uuid != self.uuid {
+            let err_msg = format!("Server registered us as {} instead of {}",
+                                  client_uuid, self.uuid);
+            return Err(ClientError::ServerRefusedError(ErrorCode::UuidMismatch,
+                                                       err_msg));
+        }
         
-        // update UUID
+        // update UUID and lease
         self.uuid = client_uuid;
+        self.lease_ttl = Duration::from_secs(ttl);
 
         Ok(client_uuid)
     }
 
-    /// queries the central index server for a uuid, and adds the new mapping
-    /// to the caller's `peer_map`
+    /// renews the client's lease with the server, returning the granted ttl
+    async fn send_heartbeat(&self) -> Result<Duration, ClientError> {
+        let ttl = self.lease_ttl.as_secs();
+        let timestamp = request_timestamp();
+        let payload = Heartbeat::signing_payload(&self.uuid, ttl, timestamp);
+
+        let request = RequestBody::Heartbeat(Heartbeat {
+            uuid: self.uuid,
+            ttl,
+            timestamp,
+            signature: crypto::sign(&self.identity, &payload),
+        });
+
+        match self.server_request(request).await? {
+            ResponseBody::LeaseRenewed { ttl } => Ok(Duration::from_secs(ttl)),
+            other => Err(unexpected_response(other)),
+        }
+    }
+
+    /// tells the server that the client is leaving, so that peers are no 
+    /// longer routed to its socket. Done upon graceful shutdown.
+    pub async fn deregister_from_server(&self) -> Result<(), ClientError> {
+        let timestamp = request_timestamp();
+        let payload = Deregistration::signing_payload(&self.uuid, timestamp);
+
+        let request = RequestBody::Deregistration(Deregistration {
+            uuid: self.uuid,
+            timestamp,
+            signature: crypto::sign(&self.identity, &payload),
+        });
+
+        match self.server_request(request).await? {
+            ResponseBody::Deregistered => Ok(()),
+            other => Err(unexpected_response(other)),
+        }
+    }
+
+    /// queries the central index server for a uuid's address and public key
     ///
     /// `peer_uuid`: queried uuid
     async fn server_lookup_uuid(&self, peer_uuid: &Uuid) 
-        -> Result<SocketAddr, ClientError> {
+        -> Result<PeerEntry, ClientError> {
+        let request = RequestBody::Query(Query { 
+            queried_uuid: *peer_uuid,
+            watch: self.watch(&peer_uuid.to_string()),
+        });
+        let response = self.server_request(request).await?;
+        self.peer_entry_from(response)
+    }
+
+    /// asks the central index server which peer holds a username. The peer
+    /// is cached in `peer_map` if it is online, but its UUID is returned
+    /// either way
+    ///
+    /// `username`: the username, without the leading `@`
+    pub async fn server_lookup_name(&self, username: &str) 
+        -> Result<Uuid, ClientError> {
+        let request = RequestBody::LookupByName(LookupByName {
+            username: username.to_string(),
+            watch: self.watch(username),
+        });
+        let response = match self.server_request(request).await {
+            Ok(response) => response,
+            Err(ClientError::ServerRefusedError(ErrorCode::UnknownUsername, 
+                                                _)) => 
+                return Err(ClientError::PeerNotFoundError(
+                        format!("No peer named @{}", username))),
+            Err(err) => return Err(err),
+        };
 
-        // prepare json request
-        let mut query           = JsonValue::new_object();
-        query["req_type"]       = JsonValue::from("query");
-        query["queried_uuid"]   = JsonValue::from(peer_uuid.to_string());
+        let peer_uuid = match response {
+            ResponseBody::PeerInfo { uuid, .. } => uuid,
+            other => return Err(unexpected_response(other)),
+        };
+        if let Ok(peer_entry) = self.peer_entry_from(response) {
+            self.punch_if_needed(&peer_uuid, &peer_entry).await;
+            self.peer_map.lock().await.insert(peer_uuid, peer_entry);
+        }
+        Ok(peer_uuid)
+    }
 
-        // send query to server
-        let host_addr = SocketAddr::from(([127, 0, 0, 1], HOST_PORT));
-        
-        // socket for sending traffic to server
-        let out_socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    /// turns the answer to a lookup into what we need to reach the peer.
+    /// Fails unless the peer is online
+    ///
+    /// `response`: the server's answer
+    fn peer_entry_from(&self, response: ResponseBody) 
+        -> Result<PeerEntry, ClientError> {
+        let (status, address, observed_addr, public_key, codecs) = 
+            match response {
+            ResponseBody::PeerInfo { 
+                status, address, observed_address, public_key, codecs, .. 
+            } => (status, address, observed_address, public_key, codecs),
+            other => return Err(unexpected_response(other)),
+        };
 
-        match out_socket.send_to(query.dump()
-                .as_bytes(), host_addr).await {
-            Ok(n_bytes) => {
-                println!("send {} bytes to central index server", n_bytes);
-            }
-            Err(err) => {
-                let err_msg = format!(
-                    "Unable to reach server. {}", err);
-                return Err(ClientError::ServerUnavailableError(err_msg));
-            }
+        match status {
+            PeerStatus::Online => (),
+            PeerStatus::Offline => return Err(ClientError::PeerOfflineError(
+                    "Peer's registration has expired".to_string())),
+            PeerStatus::Unknown => return Err(ClientError::PeerNotFoundError(
+                    "No peer matching provided UUID".to_string())),
         }
 
-        // buffer for server response
-        let mut buf = [0; 1024];
-        let (size, _) = match out_socket.recv_from(&mut buf).await {
-            Ok((len, addr)) => (len, addr),
-            Err(err) => {
-                let err_msg = format!(
-                    "Error waiting for server response. {}", err);
-                return Err(ClientError::ServerUnavailableError(err_msg));
-            }
+        let public_key = match public_key.as_deref()
+            .and_then(crypto::parse_public_key) {
+            Some(valid_key) => valid_key,
+            None => return Err(ClientError::PeerNotFoundError(
+                    "Peer has no valid public key".to_string())),
         };
 
-        // unwrap assumes that error sends valid response.
-        // TODO: Consider error handling here. For the time being, I assume
-        // that server functions correctly and following the protocol perfectly
-        let server_resp = 
-            String::from_utf8(buf[..size].to_vec()).unwrap();
-        let server_resp = json::parse(&server_resp).unwrap();
+        match address {
+            Some(addr) => Ok(PeerEntry { 
+                addr, 
+                observed_addr,
+                relayed: false,
+                public_key, 
+                codec: Codec::negotiate(&self.codecs, &codecs),
+                resolved_at: Instant::now(),
+            }),
+            None => Err(ClientError::PeerNotFoundError(
+                    "Server sent no address for online peer".to_string())),
+        }
+    }
+
+    /// sends a request to the central index server and waits for its response.
+    /// An error response is turned into a `ServerRefusedError`
+    ///
+    /// The request goes out of the listening socket, so the server sees it
+    /// come from the address peers know us by, and spawn_demux() hands the
+    /// response over. The request is sent again if no response arrives 
+    /// within `server_timeout`, which doubles every time, up to 
+    /// `server_retries` times before failing with a `ServerTimeoutError`.
+    /// The server answers a retransmission of a request it handled already
+    /// with the same response, so signed requests can be retransmitted too.
+    ///
+    /// `request`: the request to send
+    pub(crate) async fn server_request(&self, request: RequestBody) 
+        -> Result<ResponseBody, ClientError> {
+        // retransmissions keep the id, so any of them may be answered
+        let id = Uuid::new_v4();
+        let mut request = Request::new(request);
+        request.id = Some(id);
+
+        let (response_sender, mut response_receiver) = oneshot::channel();
+        self.pending_requests.lock().await.insert(id, response_sender);
+        let response = self.retransmit_request(&request.to_bytes(), 
+                                               &mut response_receiver).await;
+        self.pending_requests.lock().await.remove(&id);
 
-        let recv_ip = &server_resp["address"].to_string();
-        if recv_ip == "nil" {
-            return Err(ClientError::PeerNotFoundError(
-                    "No peer matching provided UUID".to_string()));
+        match response?.body {
+            ResponseBody::Error { code, message } => 
+                Err(ClientError::ServerRefusedError(code, message)),
+            body => Ok(body),
         }
+    }
+
+    /// sends a request to the server until its response is handed over
+    ///
+    /// `request`: the encoded request
+    /// `response_receiver`: where spawn_demux() hands the response over
+    async fn retransmit_request(&self, request: &[u8], 
+                                response_receiver: 
+                                    &mut oneshot::Receiver<Response>)
+        -> Result<Response, ClientError> {
+        let mut timeout = self.server_timeout;
+        for _ in 0..=self.server_retries {
+            match self.send_from_listener(request, self.server_addr).await {
+                Ok(n_bytes) => {
+                    println!("send {} bytes to central index server", 
+                             n_bytes);
+                }
+                Err(err) => {
+                    let err_msg = format!(
+                        "Unable to reach server. {}", err);
+                    return Err(ClientError::ServerUnavailableError(err_msg));
+                }
+            }
 
-        // return found socket address. Shouldn't fail at this point in time
-        match recv_ip.to_string().parse::<SocketAddr>() {
-            Ok(addr) => Ok(addr),
-            Err(_) => Err(ClientError::ServerUnavailableError("Fuck"
-                                                              .to_string())),
+            match time::timeout(timeout, &mut *response_receiver).await {
+                Ok(Ok(response)) => return Ok(response),
+                Ok(Err(_)) => {
+                    let err_msg = "No longer listening for responses";
+                    return Err(ClientError::ServerUnavailableError(
+                            err_msg.to_string()));
+                },
+                Err(_) => timeout *= 2,
+            }
+        }
+
+        let err_msg = format!("No response from {} after {} attempts", 
+                              self.server_addr, self.server_retries + 1);
+        Err(ClientError::ServerTimeoutError(err_msg))
+    }
+
+    /// keeps the client's registration alive by renewing its lease a few 
+    /// times per ttl. Registers again if the server no longer knows the 
+    /// client. Every beat also punches the server, which keeps any NAT 
+    /// mapping of the listening socket open
+    pub async fn heartbeat_loop(&mut self) {
+        loop {
+            time::sleep(self.lease_ttl / 3).await;
+
+            match self.send_heartbeat().await {
+                Ok(ttl) => self.lease_ttl = ttl,
+                Err(ClientError::ServerRefusedError(ErrorCode::UnknownPeer, 
+                                                    msg)) => {
+                    println!("Heartbeat refused: {}. Registering again...", 
+                             msg);
+                    match self.register_with_server().await {
+                        Ok(_) => self.fetch_mail_command().await,
+                        Err(err) => 
+                            println!("Error registering with server: {}", err),
+                    }
+                },
+                Err(err) => println!("Error renewing lease: {}", err),
+            }
+            if let Err(err) = self.send_punch(None).await {
+                println!("Error punching server: {}", err);
+            }
         }
     }
 
     /// listens for incoming traffic, posts the messages in the recv_queue
-    /// for display by display_loop()
-    pub async fn incoming_traff_loop(&mut self){
-        let mut recv_buf: [u8; 1024] = [0; 1024];
-        let arc_ref = Arc::clone(&self.recv_queue);
+    /// for display by display_loop(). Every data message is acknowledged to
+    /// its sender, duplicates caused by retransmission are only acknowledged.
+    /// Fragmented messages are reassembled first, and every other message is
+    /// handled in a task of its own by handle_message(), so that a lookup of
+    /// its sender doesn't hold up the rest.
+    ///
+    /// Acks are handed over to the task waiting for them, and probes are
+    /// dropped. What the server pushes goes to handle_server_push(), and
+    /// the datagrams it relayed from other peers are handled like the others,
+    /// except that they are acknowledged through the relay too. Responses to
+    /// our requests never get here, spawn_demux() hands them over.
+    ///
+    /// `incoming`: the datagrams received on the listening socket, from
+    /// spawn_demux()
+    pub async fn incoming_traff_loop(&mut self, 
+                                     mut incoming: mpsc::Receiver<Datagram>) {
+        let mut reassembler = Reassembler::new(REASSEMBLY_TIMEOUT, 
+                                               self.max_message_size);
 
         // loop and ask client for message to send
         'main_loop: loop {
-            let (recv_len, _) = self.listening_socket
-                .lock()
-                .await
-                .recv_from(&mut recv_buf)
-                .await.unwrap();
-            println!("Received {}B", recv_len);
-
-            // "functional code is so readable"
-            let json_data = json::parse(&String::from_utf8(recv_buf[..recv_len]
-                .to_vec()).unwrap()).unwrap();
-
-            let msg = match Message::from_json(json_data) {
-                Ok(msg) => msg,
+            let (recv_buf, src_addr) = match incoming.recv().await {
+                Some(received) => received,
+                None => return,
+            };
+
+            // probes have done their job by making it through
+            if punch::is_probe(&recv_buf) {
+                continue 'main_loop;
+            }
+
+            let relayed;
+            let (datagram, route) = if src_addr == self.server_addr {
+                relayed = match self.handle_server_push(&recv_buf) {
+                    Some(relayed) => relayed,
+                    None => continue 'main_loop,
+                };
+                (relayed.1.as_slice(), Route::Relay(relayed.0))
+            } else {
+                (recv_buf.as_slice(), Route::Direct(src_addr))
+            };
+
+            // relayed fragments all come from the server, they count against
+            // the limits of the peer that signed the relay request instead
+            let msg_bytes = match reassembler.push(route, datagram,
+                                                   Instant::now().into_std()) {
+                Ok(Some(msg_bytes)) => msg_bytes,
+                // more fragments to come
+                Ok(None) => continue 'main_loop,
+                Err(err) => {
+                    println!("Dropping datagram from {}: {:?}", src_addr, err);
+                    continue 'main_loop;
+                }
+            };
+            println!("Received {}B", msg_bytes.len());
+
+            // whichever codec the sender picked, the ack goes back in it
+            let (msg, codec) = match Message::decode(&msg_bytes, &self.keys) {
+                Ok(decoded) => decoded,
                 Err(err) => {
                     println!("{}", err);
                     continue 'main_loop;
                 }
             };
-            
-            // different scope so that lock can be dropped before sleep
-            {
-                arc_ref.lock().await.push_back(msg);
+
+            // the server relays on behalf of the peer that signed the relay
+            // request, anybody else's message is forged
+            if let Route::Relay(relayed_from) = route {
+                if msg.src_uuid != relayed_from {
+                    println!("\x1b[31mDropping message from {} relayed on \
+                             behalf of {}\x1b[0m", msg.src_uuid,
+                             relayed_from);
+                    continue 'main_loop;
+                }
+            }
+
+            // acks are handled by the sending task, if it's still waiting.
+            // It knows which key the ack must come from, no lookup needed
+            if msg.msg_type == MessageType::Ack {
+                self.hand_over_ack(msg, route).await;
+                continue 'main_loop;
             }
 
-            // sleep for 0.2 seconds. Dunno seemed like a reasonable time
-            let _ = time::sleep(Duration::from_millis(200)).await;
+            // checking the sender's key may take a lookup, which mustn't hold
+            // up the datagrams behind it. Whatever doesn't fit is dropped, and
+            // retransmitted by its sender
+            let permit = match Arc::clone(&self.message_tasks)
+                .try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    println!("Dropping message from {}, too many are being \
+                             handled", msg.src_uuid);
+                    continue 'main_loop;
+                }
+            };
+            let client = self.clone();
+            tokio::spawn(async move {
+                client.handle_message(msg, codec, route).await;
+                drop(permit);
+            });
+        }
+    }
+
+    /// hands a received ack over to the task delivering the message it
+    /// acknowledges, provided it comes from the peer that message was for.
+    /// Acks of messages nobody waits for anymore are dropped
+    ///
+    /// `ack`: the received ack, authenticated already
+    /// `route`: how the ack came
+    async fn hand_over_ack(&self, ack: Message, route: Route) {
+        let mut pending_acks = self.pending_acks.lock().await;
+        match pending_acks.get(&ack.msg_id) {
+            Some(pending) if pending.peer_uuid == ack.src_uuid 
+                && Some(pending.public_key) == ack.src_public_key => (),
+            Some(_) => {
+                println!("\x1b[31mDropping ack of {} from {}, which it wasn't \
+                         sent to\x1b[0m", ack.msg_id, ack.src_uuid);
+                return;
+            },
+            None => return,
         }
+        let pending = pending_acks.remove(&ack.msg_id).unwrap();
+        let _ = pending.ack_sender.send((ack, route));
+    }
+
+    /// handles an authenticated message other than an ack: checks its
+    /// sender's key and group tag, acknowledges it, and posts data messages
+    /// for display. File messages are answered in their ack. They are
+    /// idempotent, so retransmissions are simply handled again
+    ///
+    /// `msg`: the received message
+    /// `codec`: the codec it came in, which the ack goes back in
+    /// `route`: how it came, which the ack goes back by
+    async fn handle_message(&self, msg: Message, codec: Codec, route: Route) {
+        if !self.has_known_key(&msg).await || !self.accept_group_tag(&msg)
+            .await {
+            return;
+        }
+
+        let ack = match msg.msg_type {
+            MessageType::Data => msg.new_ack(),
+            _ => {
+                let reply = self.file_receiver.handle(&msg).await;
+                msg.new_reply(&transfer::to_data(&reply))
+            },
+        };
+        // authenticated, so `src_public_key` belongs to the sender
+        let src_public_key = msg.src_public_key.unwrap();
+        let ack = ack.encode(codec, &self.keys, &src_public_key);
+        if let Err(err) = self.send_datagram(route, &ack).await {
+            println!("Could not acknowledge message: {}", err);
+        }
+        if msg.msg_type == MessageType::Data {
+            self.queue_message(msg).await;
+        }
+    }
+
+    /// refuses messages whose key isn't the one the server has for the 
+    /// sender's UUID. Senders we haven't heard of are looked up first, so a
+    /// UUID can't be claimed by whoever writes it in a message. Those the
+    /// server doesn't know are refused for `UNKNOWN_SENDER_TTL` without
+    /// asking again. The message must be authenticated already
+    ///
+    /// `msg`: the received message
+    pub(crate) async fn has_known_key(&self, msg: &Message) -> bool {
+        if let Some(until) = self.unknown_senders.lock().await
+            .get(&msg.src_uuid) {
+            if Instant::now() < *until {
+                return false;
+            }
+        }
+
+        let known_key = match self.resolve_peer(&msg.src_uuid).await {
+            Ok(peer_entry) => Ok(peer_entry.public_key),
+            // e.g. mail left before the sender went offline
+            Err(ClientError::PeerOfflineError(_)) => 
+                self.offline_peer_key(&msg.src_uuid).await
+                    .map(|(public_key, _)| public_key),
+            Err(err) => Err(err),
+        };
+        match known_key {
+            Ok(known_key) if Some(known_key) == msg.src_public_key => true,
+            Ok(_) => {
+                println!("\x1b[31mDropping message claiming to be from \
+                         {} sent with an unknown key\x1b[0m", 
+                         msg.src_uuid);
+                false
+            },
+            Err(err) => {
+                println!("\x1b[31mDropping message from {}, its key can't \
+                         be checked: {}\x1b[0m", msg.src_uuid, err);
+                if let ClientError::PeerNotFoundError(_) = err {
+                    self.remember_unknown_sender(&msg.src_uuid).await;
+                }
+                false
+            },
+        }
+    }
+
+    /// remembers that the server doesn't know `peer_uuid`, so that messages
+    /// claiming to be from it are refused for a while without asking again
+    ///
+    /// `peer_uuid`: the uuid of the sender
+    async fn remember_unknown_sender(&self, peer_uuid: &Uuid) {
+        let now = Instant::now();
+        let mut unknown_senders = self.unknown_senders.lock().await;
+        unknown_senders.retain(|_, until| now < *until);
+        // past that, forged senders just cost a lookup each
+        if unknown_senders.len() < UNKNOWN_SENDERS_CAPACITY {
+            unknown_senders.insert(*peer_uuid, now + UNKNOWN_SENDER_TTL);
+        }
+    }
+
+    /// checks the group tag of a received message, and learns about the
+    /// group from it. A member is told about the group by its messages, and
+    /// about changes to the group by the messages sent after them. Our copy
+    /// is only replaced by the creator's or a newer one, see 
+    /// Group::supersedes(). False if the sender or we aren't members, or if
+    /// a new group can't be learned, see learn_group(). The message is
+    /// dropped then
+    ///
+    /// `msg`: the received message
+    pub(crate) async fn accept_group_tag(&self, msg: &Message) -> bool {
+        if let Some(tag) = &msg.group {
+            let mut groups = self.groups.lock().await;
+            let group = match groups.get(&tag.id) {
+                // e.g. sent before a change we know about already
+                Some(local) if !tag.supersedes(local, &msg.src_uuid) => 
+                    local.clone(),
+                _ => tag.clone(),
+            };
+            if !group.is_member(&msg.src_uuid) 
+                || !group.is_member(&self.uuid) {
+                println!("\x1b[31mDropping message from {} to group {} \
+                         that it or we aren't members of\x1b[0m", 
+                         msg.src_uuid, group.id);
+                return false;
+            }
+            if !groups.contains_key(&group.id) 
+                && !self.learn_group(&group, &msg.src_uuid).await {
+                return false;
+            }
+            groups.insert(group.id, group);
+        }
+        true
+    }
+
+    /// whether a group we didn't know yet may be added to our groups, and
+    /// counts it against the peer that told us about it. Its id mustn't be
+    /// one a peer is addressed with, or messages to that peer would go to
+    /// the group instead, and a peer may only tell us about 
+    /// `MAX_GROUPS_PER_PEER` groups
+    ///
+    /// `group`: the group
+    /// `src_uuid`: the peer whose message carried it
+    async fn learn_group(&self, group: &Group, src_uuid: &Uuid) -> bool {
+        if group.id == self.uuid || group.is_member(&group.id) 
+            || self.is_known_peer(&group.id).await {
+            println!("\x1b[31mDropping message from {} to group {} whose id \
+                     is a peer's\x1b[0m", src_uuid, group.id);
+            return false;
+        }
+        let mut groups_learned = self.groups_learned.lock().await;
+        let learned = groups_learned.entry(*src_uuid).or_insert(0);
+        if *learned >= MAX_GROUPS_PER_PEER {
+            println!("\x1b[31mDropping message from {} to group {}, it told \
+                     us about {} groups already\x1b[0m", 
+                     src_uuid, group.id, MAX_GROUPS_PER_PEER);
+            return false;
+        }
+        *learned += 1;
+        true
+    }
+
+    /// whether `uuid` is a peer we know of, i.e. one we talked to or have a
+    /// contact for
+    pub(crate) async fn is_known_peer(&self, uuid: &Uuid) -> bool {
+        self.peer_map.lock().await.contains_key(uuid)
+            || self.contacts.lock().await.alias_of(uuid).is_some()
+    }
+
+    /// posts a received data message in the recv_queue for display, unless
+    /// it is a retransmission whose original was already received. Returns
+    /// whether it was posted
+    ///
+    /// `msg`: the received message
+    pub(crate) async fn queue_message(&self, msg: Message) -> bool {
+        {
+            let mut seen_msg_ids = self.seen_msg_ids.lock().await;
+            if seen_msg_ids.contains(&msg.msg_id) {
+                return false;
+            }
+            if seen_msg_ids.len() == SEEN_MSG_IDS_CAPACITY {
+                seen_msg_ids.pop_front();
+            }
+            seen_msg_ids.push_back(msg.msg_id);
+        }
+        self.recv_queue.lock().await.push_back(msg);
+        true
     }
 
     /// sends outgoing traffic. `self` is mutable since a server lookup happens
@@ -282,27 +1283,57 @@ impl Client {
             println!("=======================================================");
             println!("Please enter a message >> ");
             let _ = stdin().read_line(&mut msg).unwrap();
-            println!("");
+            println!();
+
+            if let Some(args) = msg.trim().strip_prefix("/send ") {
+                self.send_file_command(args).await;
+                continue 'main_loop;
+            }
+            if let Some(args) = msg.trim().strip_prefix("/group") {
+                self.group_command(args).await;
+                continue 'main_loop;
+            }
+            if let Some(args) = msg.trim().strip_prefix("/contact") {
+                self.contact_command(args).await;
+                continue 'main_loop;
+            }
 
-            println!("Please enter a source uuid >> ");
+            println!("Please enter a recipient uuid, alias or @username >> ");
             let _ = stdin().read_line(&mut dst_uuid).unwrap();
-            println!("");
+            println!();
 
             // attempt to parse uuid. `len - 1` to remove trailiing '\n' from
             // pressing ENTER in cli
             let dst_uuid = dst_uuid.to_string()
                 .replace(" ", "")
                 .replace("\n", "");
-            let peer_uuid: Uuid = match dst_uuid.parse() {
+            let peer_uuid: Uuid = match self.resolve_name(&dst_uuid).await {
                 Ok(valid_uuid) => valid_uuid,
-                Err(_) => {
-                    println!("\x1b[31mError parsing UUID. Try again.\x1b[0m");
+                Err(err) => {
+                    println!("\x1b[31m{}. Try again.\x1b[0m", err);
                     continue 'main_loop;
                 },
             };
 
+            // a group id is addressed like a peer uuid, but never takes the
+            // place of a peer we talked to. Contacts may alias groups
+            if !self.peer_map.lock().await.contains_key(&peer_uuid)
+                && self.groups.lock().await.contains_key(&peer_uuid) {
+                self.send_group_command(&peer_uuid, &msg).await;
+                continue 'main_loop;
+            }
+
             match self.send_message(&peer_uuid, &msg).await {
-                Ok(_) => println!("\x1b[1mSent message successfully...\x1b[0m"),
+                Ok(DeliveryStatus::Delivered) => 
+                    println!("\x1b[1mMessage delivered directly...\x1b[0m"),
+                Ok(DeliveryStatus::Relayed) => 
+                    println!("\x1b[1mMessage delivered through the server's \
+                             relay...\x1b[0m"),
+                Ok(DeliveryStatus::Deposited) => 
+                    println!("\x1b[1mRecipient is offline, message left in \
+                             its mailbox...\x1b[0m"),
+                Ok(_) => 
+                    println!("\x1b[31mMessage was not acknowledged.\x1b[0m"),
                 Err(err) => {
                     println!("Error sending message: {}", err);
                     continue 'main_loop;
@@ -311,6 +1342,32 @@ impl Client {
         }
     }
 
+    /// handles `/send <path> <uuid or alias>`, which sends the file at `path`
+    ///
+    /// `args`: everything after `/send`
+    async fn send_file_command(&self, args: &str) {
+        // the uuid is the last word, so the path may contain spaces
+        let (path, name) = match args.trim().rsplit_once(' ') {
+            Some((path, name)) => (path.trim(), name),
+            None => {
+                println!("\x1b[31mTry: /send <path> <uuid or alias>\x1b[0m");
+                return;
+            }
+        };
+        let peer_uuid = match self.resolve_name(name).await {
+            Ok(peer_uuid) => peer_uuid,
+            Err(err) => {
+                println!("\x1b[31m{}\x1b[0m", err);
+                return;
+            }
+        };
+
+        match self.send_file(&peer_uuid, Path::new(path)).await {
+            Ok(_) => println!("\x1b[1mFile delivered and verified...\x1b[0m"),
+            Err(err) => println!("Error sending file: {}", err),
+        }
+    }
+
     /// checks out the recv_queue to see if there are incoming messages that 
     /// need to be displayed, and displays them
     pub async fn display_loop(&mut self) {
@@ -319,13 +1376,22 @@ impl Client {
             {
                 // inner scope so that the thread doesn't sleep with the lock.
                 let mut locked_queue = arc_ref.lock().await;
+                let contacts = self.contacts.lock().await;
                 // display all messages if there are any available
                 while !locked_queue.is_empty() {
                     let msg = locked_queue.pop_back().unwrap();
                     let disp_line = 
                         "=================================================";
                     println!("{}", disp_line);
-                    println!("Message from \x1b[36m{}\x1b[0m", msg.src_uuid);
+                    let sender = contacts.display_name(&msg.src_uuid);
+                    match &msg.group {
+                        Some(group) => println!(
+                            "Message from \x1b[36m{}\x1b[0m in group \
+                            \x1b[35m{}\x1b[0m ({})", 
+                            sender, group.name, group.id),
+                        None => println!("Message from \x1b[36m{}\x1b[0m", 
+                                         sender),
+                    }
                     println!("\x1b[1mSent:\x1b[0m[{}]", msg.creation_time);
                     println!("\x1b[1mContent:\x1b[0m");
                     println!("{}", msg.data);
@@ -338,6 +1404,36 @@ impl Client {
     }
 }
 
+/// the error for a response of the wrong type, i.e. one that doesn't answer
+/// the request sent
+pub(crate) fn unexpected_response(response: ResponseBody) -> ClientError {
+    let err_msg = format!("Unexpected server response: {:?}", response);
+    ClientError::InvalidResponseError(err_msg)
+}
+
+/// the address to bind a socket that talks to `addr` to: any interface and
+/// any port, of the same family as `addr`
+pub(crate) fn unspecified_for(addr: &SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    }
+}
+
+/// milliseconds since the unix epoch, used to timestamp signed requests. The
+/// server refuses timestamps that aren't newer than the last one it accepted,
+/// so requests made within the same millisecond still get increasing ones
+pub(crate) fn request_timestamp() -> u64 {
+    static LAST_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+        .as_millis() as u64;
+    let last = LAST_TIMESTAMP.fetch_update(Ordering::Relaxed, 
+                                           Ordering::Relaxed,
+                                           |last| Some(now.max(last + 1)))
+        .unwrap();
+    now.max(last + 1)
+}
+
 use std::error;
 use std::fmt;
 
@@ -345,9 +1441,18 @@ use std::fmt;
 #[derive(Debug, Clone)]
 pub enum ClientError {
     ServerUnavailableError(String),
+    ServerTimeoutError(String),
+    InvalidResponseError(String),
     PeerNotFoundError(String),
+    PeerOfflineError(String),
     UdpFailureError(String),
+    MessageTooLargeError(String),
+    FileTransferError(String),
+    GroupError(String),
+    ContactError(String),
     ClientCreationError(String),
+    ServerRefusedError(ErrorCode, String),
+    IdentityError(String),
 }
 
 impl error::Error for ClientError {}
@@ -357,13 +1462,129 @@ impl fmt::Display for ClientError {
         match self {
             ClientError::ServerUnavailableError(msg) => 
                 write!(f, "ServerUnavailableError: {}", msg),
+            ClientError::ServerTimeoutError(msg) => 
+                write!(f, "ServerTimeoutError: {}", msg),
+            ClientError::InvalidResponseError(msg) => 
+                write!(f, "InvalidResponseError: {}", msg),
             ClientError::PeerNotFoundError(msg) => 
                 write!(f, "PeerNotFoundError: {}", msg),
+            ClientError::PeerOfflineError(msg) => 
+                write!(f, "PeerOfflineError: {}", msg),
             ClientError::UdpFailureError(msg) => 
                 write!(f, "UdpFailureError: {}", msg),
+            ClientError::MessageTooLargeError(msg) => 
+                write!(f, "MessageTooLargeError: {}", msg),
+            ClientError::FileTransferError(msg) => 
+                write!(f, "FileTransferError: {}", msg),
+            ClientError::GroupError(msg) => 
+                write!(f, "GroupError: {}", msg),
+            ClientError::ContactError(msg) => 
+                write!(f, "ContactError: {}", msg),
             ClientError::ClientCreationError(msg) => 
                 write!(f, "ClientCreationError: {}", msg),
+            ClientError::ServerRefusedError(code, msg) => 
+                write!(f, "ServerRefusedError: {} ({})", msg, code),
+            ClientError::IdentityError(msg) => 
+                write!(f, "IdentityError: {}", msg),
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_timestamps_increase() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+            .as_millis() as u64;
+        // far more than fit in a millisecond, none may repeat
+        let timestamps: Vec<u64> = (0..1000).map(|_| request_timestamp())
+            .collect();
+        assert!(timestamps[0] >= now);
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    /// a client listening on a port of its own, with a UUID
+    async fn test_client() -> Client {
+        let listen_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let mut client = Client::build(listen_addr).await.unwrap();
+        client.uuid = Uuid::new_v4();
+        client
+    }
+
+    /// a message from `src_uuid` tagged with `group`
+    fn group_message(client: &Client, src_uuid: Uuid, group: &Group)
+        -> Message {
+        let mut msg = Message::new(client.uuid, src_uuid, "hello").unwrap();
+        msg.group = Some(group.clone());
+        msg
+    }
+
+    #[tokio::test]
+    async fn finished_lookups_are_removed() {
+        let mut client = test_client().await;
+        // nothing answers there, so every lookup fails
+        client.server_addr = SocketAddr::from(([127, 0, 0, 1], 9));
+        client.server_timeout = Duration::from_millis(10);
+        client.server_retries = 0;
+
+        let peer = Uuid::new_v4();
+        let (first, second) = tokio::join!(client.resolve_peer(&peer),
+                                           client.resolve_peer(&peer));
+        assert!(first.is_err() && second.is_err());
+        assert!(client.lookups.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn groups_cant_take_the_place_of_peers() {
+        let client = test_client().await;
+        let (peer, contact) = (Uuid::new_v4(), Uuid::new_v4());
+        client.contacts.lock().await.add("alice", contact).unwrap();
+
+        for id in [peer, contact, client.uuid] {
+            let mut group = Group::new("team", peer, &[client.uuid]);
+            group.id = id;
+            let msg = group_message(&client, peer, &group);
+            assert!(!client.accept_group_tag(&msg).await, "{}", id);
+        }
+        assert!(client.groups.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn peers_can_only_make_up_so_many_groups() {
+        let client = test_client().await;
+        let peer = Uuid::new_v4();
+        for _ in 0..MAX_GROUPS_PER_PEER {
+            let group = Group::new("team", peer, &[client.uuid]);
+            let msg = group_message(&client, peer, &group);
+            assert!(client.accept_group_tag(&msg).await);
+            // its messages to groups we know don't count
+            assert!(client.accept_group_tag(&msg).await);
+        }
+        let group = Group::new("team", peer, &[client.uuid]);
+        let msg = group_message(&client, peer, &group);
+        assert!(!client.accept_group_tag(&msg).await);
+        assert_eq!(client.groups.lock().await.len(), MAX_GROUPS_PER_PEER);
+    }
+
+    #[test]
+    fn oldest_deliveries_are_forgotten() {
+        let mut deliveries = Deliveries::default();
+        let msg_ids: Vec<Uuid> = (0..DELIVERIES_CAPACITY + 1)
+            .map(|_| Uuid::new_v4())
+            .collect();
+        for msg_id in &msg_ids {
+            deliveries.insert(*msg_id, DeliveryStatus::Pending);
+        }
+        // updates don't count as new messages
+        deliveries.insert(msg_ids[1], DeliveryStatus::Delivered);
+
+        assert_eq!(deliveries.statuses.len(), DELIVERIES_CAPACITY);
+        assert_eq!(deliveries.get(&msg_ids[0]), None);
+        assert_eq!(deliveries.get(&msg_ids[1]), 
+                   Some(DeliveryStatus::Delivered));
+        assert_eq!(deliveries.get(&msg_ids[DELIVERIES_CAPACITY]), 
+                   Some(DeliveryStatus::Pending));
+    }
+}
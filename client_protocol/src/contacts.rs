@@ -0,0 +1,262 @@
+/*
+ * File: contacts.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: the contact book, mapping aliases picked by the user to the
+ * UUIDs of peers, and how it is stored on disk
+ */
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+use json::JsonValue;
+use uuid::Uuid;
+use crate::client::{Client, ClientError};
+
+/// aliases for peers (or groups), so that the user doesn't have to type
+/// UUIDs. Every change is written straight to the contacts file, if any
+#[derive(Debug, Default)]
+pub struct ContactBook {
+    path: Option<PathBuf>,
+    contacts: BTreeMap<String, Uuid>,
+}
+
+impl ContactBook {
+    /// loads the contacts written to `path` by earlier runs, and keeps them
+    /// in it from now on. A missing file is an empty contact book
+    ///
+    /// `path`: the contacts file
+    pub fn load(path: &Path) -> Result<ContactBook, ClientError> {
+        let mut contact_book = ContactBook {
+            path: Some(path.to_path_buf()),
+            contacts: BTreeMap::new(),
+        };
+        if !path.exists() {
+            return Ok(contact_book);
+        }
+
+        let json_data = fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| json::parse(&contents)
+                      .map_err(|err| err.to_string()));
+        let json_data = match json_data {
+            Ok(json_data) => json_data,
+            Err(err) => {
+                let err_msg = format!("Could not read {}: {}",
+                                      path.display(), err);
+                return Err(ClientError::ContactError(err_msg));
+            }
+        };
+
+        for (alias, uuid) in json_data.entries() {
+            if !is_valid_alias(alias) {
+                let err_msg = format!("Contact {} has an invalid alias",
+                                      alias);
+                return Err(ClientError::ContactError(err_msg));
+            }
+            match uuid.to_string().parse::<Uuid>() {
+                Ok(uuid) => contact_book.contacts.insert(alias.to_string(),
+                                                         uuid),
+                Err(_) => {
+                    let err_msg = format!("Contact {} has an invalid UUID",
+                                          alias);
+                    return Err(ClientError::ContactError(err_msg));
+                }
+            };
+        }
+        Ok(contact_book)
+    }
+
+    /// adds a contact, or points an existing alias to another UUID. Returns
+    /// the UUID the alias used to point to
+    ///
+    /// `alias`: a single word that isn't a UUID or a `@username` itself
+    /// `uuid`: the uuid of the peer
+    pub fn add(&mut self, alias: &str, uuid: Uuid)
+        -> Result<Option<Uuid>, ClientError> {
+        if !is_valid_alias(alias) {
+            let err_msg = format!("{} can't be used as an alias", alias);
+            return Err(ClientError::ContactError(err_msg));
+        }
+
+        let previous = self.contacts.insert(alias.to_string(), uuid);
+        self.save()?;
+        Ok(previous)
+    }
+
+    /// removes a contact, returning the UUID its alias pointed to
+    ///
+    /// `alias`: the alias of the contact
+    pub fn remove(&mut self, alias: &str) 
+        -> Result<Option<Uuid>, ClientError> {
+        let removed = self.contacts.remove(alias);
+        if removed.is_some() {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// the UUID typed by the user, either as an alias or as the UUID itself
+    ///
+    /// `name`: an alias or a UUID
+    pub fn resolve(&self, name: &str) -> Option<Uuid> {
+        match self.contacts.get(name) {
+            Some(uuid) => Some(*uuid),
+            None => name.parse().ok(),
+        }
+    }
+
+    /// the alias of a UUID, if it is a contact
+    pub fn alias_of(&self, uuid: &Uuid) -> Option<&str> {
+        self.contacts.iter()
+            .find(|(_, contact)| *contact == uuid)
+            .map(|(alias, _)| alias.as_str())
+    }
+
+    /// how a UUID is displayed: its alias if it has one, the UUID otherwise
+    pub fn display_name(&self, uuid: &Uuid) -> String {
+        match self.alias_of(uuid) {
+            Some(alias) => alias.to_string(),
+            None => uuid.to_string(),
+        }
+    }
+
+    /// every contact, ordered by alias
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Uuid)> {
+        self.contacts.iter()
+    }
+
+    /// writes the contacts to the contacts file, if there is one
+    fn save(&self) -> Result<(), ClientError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut json_data = JsonValue::new_object();
+        for (alias, uuid) in &self.contacts {
+            json_data[alias.as_str()] = JsonValue::from(uuid.to_string());
+        }
+        match fs::write(path, json_data.pretty(4)) {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let err_msg = format!("Could not write {}: {}",
+                                      path.display(), err);
+                Err(ClientError::ContactError(err_msg))
+            }
+        }
+    }
+}
+
+/// whether `alias` can't be mistaken for anything else typed where a peer is
+/// expected: a single word that isn't a command, a `@username` or a UUID
+fn is_valid_alias(alias: &str) -> bool {
+    !alias.is_empty() && !alias.contains(char::is_whitespace)
+        && !alias.starts_with('/') && !alias.starts_with('@')
+        && alias.parse::<Uuid>().is_err()
+}
+
+impl Client {
+    /// the UUID typed by the user, either as a contact's alias, as the UUID
+    /// itself, or as `@username`, which is looked up on the server
+    ///
+    /// `name`: an alias, a UUID or a username
+    pub async fn resolve_name(&self, name: &str) -> Result<Uuid, ClientError> {
+        let name = name.trim();
+        if let Some(username) = name.strip_prefix('@') {
+            return self.server_lookup_name(username).await;
+        }
+        match self.contacts.lock().await.resolve(name) {
+            Some(uuid) => Ok(uuid),
+            None => Err(ClientError::PeerNotFoundError(
+                    format!("No contact or UUID {}", name))),
+        }
+    }
+
+    /// resolves several names, failing if any of them is unknown
+    ///
+    /// `names`: aliases, UUIDs or usernames
+    pub async fn resolve_names(&self, names: &[&str]) 
+        -> Result<Vec<Uuid>, ClientError> {
+        let mut uuids = Vec::with_capacity(names.len());
+        for name in names {
+            uuids.push(self.resolve_name(name).await?);
+        }
+        Ok(uuids)
+    }
+
+    /// handles the `/contact` commands:
+    ///
+    /// ```text
+    /// /contact add <alias> <uuid or @username>
+    /// /contact remove <alias>
+    /// /contact list
+    /// ```
+    ///
+    /// `args`: everything after `/contact`
+    pub(crate) async fn contact_command(&self, args: &str) {
+        let args: Vec<&str> = args.split_whitespace().collect();
+
+        match args.as_slice() {
+            ["add", alias, name] => {
+                let uuid = match self.resolve_name(name).await {
+                    Ok(uuid) => uuid,
+                    Err(err) => {
+                        println!("\x1b[31m{}\x1b[0m", err);
+                        return;
+                    }
+                };
+                match self.contacts.lock().await.add(alias, uuid) {
+                    Ok(Some(previous)) => println!(
+                        "\x1b[36m{}\x1b[0m now is {} instead of {}",
+                        alias, uuid, previous),
+                    Ok(None) => println!("Added \x1b[36m{}\x1b[0m", alias),
+                    Err(err) => println!("{}", err),
+                }
+            },
+            ["remove", alias] => match self.contacts.lock().await
+                .remove(alias) {
+                Ok(Some(_)) => println!("Removed \x1b[36m{}\x1b[0m", alias),
+                Ok(None) => println!("\x1b[31mNo contact named {}\x1b[0m",
+                                     alias),
+                Err(err) => println!("{}", err),
+            },
+            ["list"] => {
+                let contacts = self.contacts.lock().await;
+                if contacts.iter().next().is_none() {
+                    println!("No contacts yet");
+                }
+                for (alias, uuid) in contacts.iter() {
+                    println!("\x1b[36m{}\x1b[0m {}", alias, uuid);
+                }
+            },
+            _ => println!("\x1b[31mTry: /contact add <alias> <uuid>, \
+                          /contact remove <alias>, /contact list\x1b[0m"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_that_look_like_something_else_are_refused() {
+        let mut contacts = ContactBook::default();
+        let uuid = Uuid::new_v4();
+        for alias in ["", "two words", "/send", "@alice", &uuid.to_string(),
+                      &uuid.simple().to_string()] {
+            assert!(contacts.add(alias, uuid).is_err(), "{}", alias);
+        }
+        assert_eq!(contacts.add("alice", uuid).unwrap(), None);
+        assert_eq!(contacts.resolve("alice"), Some(uuid));
+    }
+
+    #[test]
+    fn contacts_files_with_invalid_aliases_are_refused() {
+        let path = std::env::temp_dir()
+            .join(format!("p2p_contacts_{}.json", Uuid::new_v4()));
+        fs::write(&path, format!("{{\"@alice\": \"{}\"}}", Uuid::new_v4()))
+            .unwrap();
+        assert!(ContactBook::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
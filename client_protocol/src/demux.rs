@@ -0,0 +1,88 @@
+/*
+ * File: demux.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: the one reader of the listening socket. Hands the server's
+ * responses to the requests waiting for them, and everything else to
+ * incoming_traff_loop()
+ */
+use std::net::SocketAddr;
+use tokio::sync::{mpsc::{self, error::TrySendError}, oneshot};
+use protocol::Response;
+use protocol::fragment::MAX_UDP_PAYLOAD;
+use protocol::net;
+use crate::client::Client;
+
+// datagrams waiting for incoming_traff_loop(). Peer datagrams arriving while
+// the queue is full are dropped, their senders retransmit
+static INCOMING_QUEUE_CAPACITY: usize = 1024;
+
+/// a datagram received on the listening socket, and where it came from
+pub type Datagram = (Vec<u8>, SocketAddr);
+
+/// hands the response to a request over from the demux to the task that
+/// sent the request
+pub type PendingResponse = oneshot::Sender<Response>;
+
+impl Client {
+    /// starts reading the listening socket, which is where the server
+    /// answers too, so this must run before the first request to it.
+    /// Returns the datagrams for incoming_traff_loop(), queued until it runs
+    pub fn spawn_demux(&self) -> mpsc::Receiver<Datagram> {
+        let (incoming_sender, incoming_receiver) =
+            mpsc::channel(INCOMING_QUEUE_CAPACITY);
+        let client = self.clone();
+        tokio::spawn(async move {
+            client.demux(incoming_sender).await;
+        });
+        incoming_receiver
+    }
+
+    /// routes every datagram arriving on the listening socket. Responses
+    /// from the server carry the id of the request they answer, the other
+    /// datagrams from the server are pushes for incoming_traff_loop()
+    ///
+    /// `incoming`: where datagrams for incoming_traff_loop() go
+    async fn demux(&self, incoming: mpsc::Sender<Datagram>) {
+        let mut recv_buf = vec![0u8; MAX_UDP_PAYLOAD];
+        loop {
+            let (recv_len, src_addr) = match self.listening_socket
+                .recv_from(&mut recv_buf).await {
+                Ok(received) => received,
+                Err(err) => {
+                    println!("Error receiving datagram: {}", err);
+                    continue;
+                }
+            };
+            // IPv4 peers of a dual-stack socket are known by IPv4 addresses
+            let src_addr = net::canonical(src_addr);
+            let datagram = &recv_buf[..recv_len];
+
+            if src_addr == self.server_addr {
+                if let Ok(response @ Response { id: Some(id), .. }) =
+                    Response::from_bytes(datagram) {
+                    match self.pending_requests.lock().await.remove(&id) {
+                        Some(response_sender) => {
+                            let _ = response_sender.send(response);
+                        },
+                        // answers a retransmission, or a request given up on
+                        None => println!("Skipping stale server response"),
+                    }
+                    continue;
+                }
+            }
+
+            // never wait for incoming_traff_loop(), or the responses behind
+            // this datagram would wait too
+            match incoming.try_send((datagram.to_vec(), src_addr)) {
+                Ok(_) => (),
+                Err(TrySendError::Full(_)) => 
+                    println!("Dropping datagram from {}, too many queued", 
+                             src_addr),
+                // incoming_traff_loop() is gone, nobody is listening anymore
+                Err(TrySendError::Closed(_)) => return,
+            }
+        }
+    }
+}
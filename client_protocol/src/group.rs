@@ -0,0 +1,191 @@
+/*
+ * File: group.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: group chats. A message to a group is sent to each of its
+ * members, tagged with the group
+ */
+use tokio::task::JoinSet;
+use uuid::Uuid;
+use protocol::group::{Group, MAX_GROUP_MEMBERS};
+use crate::client::{Client, ClientError, DeliveryStatus};
+
+impl Client {
+    /// creates a group of this client and `members`, and returns it. The
+    /// members learn about the group from the first message sent to it
+    ///
+    /// `name`: displayed along with the group's messages
+    /// `members`: uuids of the other peers in the group
+    pub async fn create_group(&self, name: &str, members: &[Uuid])
+        -> Result<Group, ClientError> {
+        let mut group = Group::new(name, self.uuid, &[]);
+        for member in members {
+            if !group.add_member(*member) && !group.is_member(member) {
+                let err_msg = format!("A group has at most {} members",
+                                      MAX_GROUP_MEMBERS);
+                return Err(ClientError::GroupError(err_msg));
+            }
+        }
+
+        self.groups.lock().await.insert(group.id, group.clone());
+        Ok(group)
+    }
+
+    /// sends a message to every other member of a group at once, each copy
+    /// tagged with the group. Returns the outcome of every copy, since some
+    /// members may get the message while others don't
+    ///
+    /// `group_id`: the id of the group
+    /// `msg_data`: the message data
+    pub async fn send_group_message(&self, group_id: &Uuid, msg_data: &str)
+        -> Result<Vec<(Uuid, Result<DeliveryStatus, ClientError>)>,
+                  ClientError> {
+        let group = match self.groups.lock().await.get(group_id) {
+            Some(group) => group.clone(),
+            None => return Err(ClientError::GroupError(
+                    format!("No group with id {}", group_id))),
+        };
+
+        let mut sends = JoinSet::new();
+        for member in group.members.iter().filter(|uuid| **uuid != self.uuid) {
+            let mut msg = self.new_message(member, msg_data)?;
+            msg.group = Some(group.clone());
+
+            let client = self.clone();
+            let member = *member;
+            sends.spawn(async move {
+                (member, client.send_tracked(&msg).await)
+            });
+        }
+
+        let mut outcomes = Vec::new();
+        while let Some(outcome) = sends.join_next().await {
+            // a send task can only fail by panicking
+            outcomes.push(outcome.unwrap());
+        }
+        Ok(outcomes)
+    }
+
+    /// sends a message typed in the cli to a group, and reports which of its
+    /// members got it
+    ///
+    /// `group_id`: the id of the group
+    /// `msg_data`: the message data
+    pub(crate) async fn send_group_command(&self, group_id: &Uuid,
+                                           msg_data: &str) {
+        let outcomes = match self.send_group_message(group_id, msg_data).await {
+            Ok(outcomes) => outcomes,
+            Err(err) => {
+                println!("Error sending message: {}", err);
+                return;
+            }
+        };
+
+        let delivered = outcomes.iter()
+            .filter(|(_, outcome)|
+                    matches!(outcome, Ok(status) if status.is_delivered()))
+            .count();
+        if delivered == outcomes.len() {
+            println!("\x1b[1mMessage delivered to all {} members...\x1b[0m",
+                     delivered);
+            return;
+        }
+
+        println!("\x1b[31mMessage delivered to {} of {} members.\x1b[0m",
+                 delivered, outcomes.len());
+        let contacts = self.contacts.lock().await;
+        for (member, outcome) in outcomes {
+            let member = contacts.display_name(&member);
+            match outcome {
+                Ok(status) if status.is_delivered() => (),
+                Ok(DeliveryStatus::Deposited) => 
+                    println!("  {} is offline, left in its mailbox", member),
+                Ok(_) => println!("  {} did not acknowledge it", member),
+                Err(err) => println!("  {}: {}", member, err),
+            }
+        }
+    }
+
+    /// handles the `/group` commands:
+    ///
+    /// ```text
+    /// /group create <name> <uuid> [uuid...]
+    /// /group add <group id> <uuid>
+    /// /group remove <group id> <uuid>
+    /// /group list
+    /// ```
+    ///
+    /// Changes to a group reach its members with the next message sent to it.
+    /// Contacts' aliases and `@username`s can be used in place of any UUID.
+    ///
+    /// `args`: everything after `/group`
+    pub(crate) async fn group_command(&self, args: &str) {
+        let args: Vec<&str> = args.split_whitespace().collect();
+
+        match args.as_slice() {
+            ["create", name, members @ ..] if !members.is_empty() => {
+                let members = match self.resolve_names(members).await {
+                    Ok(members) => members,
+                    Err(err) => {
+                        println!("\x1b[31m{}\x1b[0m", err);
+                        return;
+                    }
+                };
+                match self.create_group(name, &members).await {
+                    Ok(group) => println!(
+                        "Created group \x1b[35m{}\x1b[0m, send to {} to \
+                        message it", group.name, group.id),
+                    Err(err) => println!("{}", err),
+                }
+            },
+            [command @ ("add" | "remove"), group_id, member] => {
+                let names = [*group_id, *member];
+                let (group_id, member) = match self.resolve_names(&names)
+                    .await {
+                    Ok(uuids) => (uuids[0], uuids[1]),
+                    Err(err) => {
+                        println!("\x1b[31m{}\x1b[0m", err);
+                        return;
+                    }
+                };
+                let mut groups = self.groups.lock().await;
+                let group = match groups.get_mut(&group_id) {
+                    Some(group) => group,
+                    None => {
+                        println!("\x1b[31mNo group with id {}\x1b[0m", 
+                                 group_id);
+                        return;
+                    }
+                };
+                let changed = match *command {
+                    "add" => group.add_member(member),
+                    _ => group.remove_member(&member),
+                };
+                if changed {
+                    println!("Group \x1b[35m{}\x1b[0m now has {} members",
+                             group.name, group.members.len());
+                } else {
+                    println!("\x1b[31mGroup {} was left unchanged\x1b[0m",
+                             group.name);
+                }
+            },
+            ["list"] => {
+                let groups = self.groups.lock().await;
+                let contacts = self.contacts.lock().await;
+                if groups.is_empty() {
+                    println!("No groups yet");
+                }
+                for group in groups.values() {
+                    println!("\x1b[35m{}\x1b[0m ({})", group.name, group.id);
+                    for member in &group.members {
+                        println!("  {}", contacts.display_name(member));
+                    }
+                }
+            },
+            _ => println!("\x1b[31mTry: /group create <name> <uuid>..., \
+                          /group add|remove <group id> <uuid>, \
+                          /group list\x1b[0m"),
+        }
+    }
+}
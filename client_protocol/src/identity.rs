@@ -0,0 +1,112 @@
+/*
+ * File: identity.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: a client's persistent identity, i.e. its UUID and the key
+ * material it is bound to, and how it is stored on disk
+ */
+use std::{fs, io::Write, path::Path};
+use ed25519_dalek::SigningKey;
+use json::JsonValue;
+use uuid::Uuid;
+use crate::client::ClientError;
+use protocol::crypto::{self, KeyPair};
+
+// prevent typos
+static UUID_FIELD: &str = "uuid";
+static IDENTITY_KEY_FIELD: &str = "identity_key";
+static ENCRYPTION_KEY_FIELD: &str = "encryption_key";
+
+/// everything a client needs to come back under the same UUID
+pub struct Identity {
+    pub uuid: Uuid,
+    pub signing_key: SigningKey,
+    pub keys: KeyPair,
+}
+
+impl Identity {
+    /// generates fresh key material. The UUID stays nil until the client has
+    /// registered with the server.
+    pub fn generate() -> Identity {
+        Identity {
+            uuid: Uuid::nil(),
+            signing_key: crypto::generate_identity(),
+            keys: KeyPair::generate(),
+        }
+    }
+
+    /// loads an identity previously written by `save()`
+    ///
+    /// `path`: the identity file
+    pub fn load(path: &Path) -> Result<Identity, ClientError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) => {
+                let err_msg = format!("Could not read {}: {}",
+                                      path.display(), err);
+                return Err(ClientError::IdentityError(err_msg));
+            }
+        };
+
+        let json_data = match json::parse(&contents) {
+            Ok(json_data) => json_data,
+            Err(err) => {
+                let err_msg = format!("Invalid identity file: {}", err);
+                return Err(ClientError::IdentityError(err_msg));
+            }
+        };
+
+        let uuid = json_data[UUID_FIELD].to_string().parse::<Uuid>();
+        let signing_key = decode_key(&json_data[IDENTITY_KEY_FIELD]);
+        let encryption_key = decode_key(&json_data[ENCRYPTION_KEY_FIELD]);
+        match (uuid, signing_key, encryption_key) {
+            (Ok(uuid), Some(signing_key), Some(encryption_key)) =>
+                Ok(Identity {
+                    uuid,
+                    signing_key: SigningKey::from_bytes(&signing_key),
+                    keys: KeyPair::from_secret_bytes(encryption_key),
+                }),
+            _ => Err(ClientError::IdentityError(
+                    "Identity file is missing or has malformed fields"
+                    .to_string())),
+        }
+    }
+
+    /// writes the identity to disk. The file holds secret keys, so it is only
+    /// readable by its owner.
+    ///
+    /// `path`: the identity file
+    pub fn save(&self, path: &Path) -> Result<(), ClientError> {
+        let mut json_data = JsonValue::new_object();
+        json_data[UUID_FIELD] = JsonValue::from(self.uuid.to_string());
+        json_data[IDENTITY_KEY_FIELD] =
+            JsonValue::from(hex::encode(self.signing_key.to_bytes()));
+        json_data[ENCRYPTION_KEY_FIELD] =
+            JsonValue::from(hex::encode(self.keys.secret_bytes()));
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+
+        let written = options.open(path)
+            .and_then(|mut file| file.write_all(json_data.pretty(4).as_bytes()));
+        match written {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let err_msg = format!("Could not write {}: {}",
+                                      path.display(), err);
+                Err(ClientError::IdentityError(err_msg))
+            }
+        }
+    }
+}
+
+/// decodes a hex encoded 32 byte secret key
+fn decode_key(key: &JsonValue) -> Option<[u8; 32]> {
+    hex::decode(key.as_str()?).ok()?.try_into().ok()
+}
@@ -0,0 +1,153 @@
+/*
+ * File: mailbox.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: leaving messages for offline peers in their mailbox on the
+ * server, and fetching the ones left for us
+ */
+use protocol::{
+    AckMail, Codec, Deposit, FetchMail, PeerStatus, Query, RequestBody, 
+    ResponseBody,
+};
+use protocol::crypto::{self, parse_public_key};
+use protocol::message::Message;
+use protocol::request::MAX_DEPOSIT_LEN;
+use uuid::Uuid;
+use x25519_dalek::PublicKey;
+use crate::client::{
+    request_timestamp, unexpected_response, Client, ClientError,
+};
+
+// fetches made in a row before the rest of the mail is left for next time
+static MAX_MAIL_FETCHES: usize = 32;
+
+impl Client {
+    /// leaves a message in the mailbox of an offline peer, for it to fetch
+    /// once it registers again. The message is encrypted for the peer like
+    /// any other, and has to fit in a single datagram
+    ///
+    /// `msg`: the message to leave
+    pub async fn deposit_message(&self, msg: &Message) 
+        -> Result<(), ClientError> {
+        let (public_key, codecs) = self.offline_peer_key(&msg.dst_uuid).await?;
+        let codec = Codec::negotiate(&self.codecs, &codecs);
+        let msg_bytes = msg.encode(codec, &self.keys, &public_key);
+        if msg_bytes.len() > MAX_DEPOSIT_LEN {
+            let err_msg = format!("Message is {}B, mailboxes take {}B", 
+                                  msg_bytes.len(), MAX_DEPOSIT_LEN);
+            return Err(ClientError::MessageTooLargeError(err_msg));
+        }
+
+        let request = RequestBody::Deposit(Deposit::new(
+                self.uuid, msg.dst_uuid, &msg_bytes, request_timestamp(), 
+                &self.identity));
+        match self.server_request(request).await? {
+            ResponseBody::Deposited { .. } => Ok(()),
+            other => Err(unexpected_response(other)),
+        }
+    }
+
+    /// asks the server for the key of an offline peer, and the codecs it
+    /// supports. Fails if the peer is online, or unknown
+    ///
+    /// `peer_uuid`: the uuid of the peer
+    pub(crate) async fn offline_peer_key(&self, peer_uuid: &Uuid)
+        -> Result<(PublicKey, Vec<Codec>), ClientError> {
+        // the server only hands out the key of offline peers, no address
+        let request = RequestBody::Query(Query { 
+            queried_uuid: *peer_uuid,
+            watch: None,
+        });
+        let (public_key, codecs) = match self.server_request(request).await? {
+            ResponseBody::PeerInfo { 
+                status: PeerStatus::Offline, public_key, codecs, .. 
+            } => (public_key, codecs),
+            ResponseBody::PeerInfo { .. } => 
+                return Err(ClientError::PeerNotFoundError(
+                        "Peer has no mailbox".to_string())),
+            other => return Err(unexpected_response(other)),
+        };
+        match public_key.as_deref().and_then(parse_public_key) {
+            Some(public_key) => Ok((public_key, codecs)),
+            None => Err(ClientError::PeerNotFoundError(
+                    "Peer has no valid public key".to_string())),
+        }
+    }
+
+    /// fetches the messages left for us while we were offline, posts them
+    /// in the recv_queue for display, and has the server remove them. Done
+    /// upon (re)registration. Returns how many messages were posted
+    pub async fn fetch_mail(&self) -> Result<usize, ClientError> {
+        let mut posted = 0;
+        for _ in 0..MAX_MAIL_FETCHES {
+            let timestamp = request_timestamp();
+            let payload = FetchMail::signing_payload(&self.uuid, timestamp);
+            let request = RequestBody::FetchMail(FetchMail {
+                uuid: self.uuid,
+                timestamp,
+                signature: crypto::sign(&self.identity, &payload),
+            });
+            let (messages, remaining) = match self.server_request(request)
+                .await? {
+                ResponseBody::Mail { messages, remaining } => 
+                    (messages, remaining),
+                other => return Err(unexpected_response(other)),
+            };
+            if messages.is_empty() {
+                break;
+            }
+
+            let mut ids: Vec<Uuid> = Vec::with_capacity(messages.len());
+            for mail in messages {
+                ids.push(mail.id);
+                // the server vouches for who left the message, which has to
+                // be who the message says it's from
+                let msg = match mail.decoded_message()
+                    .map(|msg_bytes| Message::decode(&msg_bytes, &self.keys)) {
+                    Some(Ok((msg, _))) if msg.src_uuid == mail.src_uuid => msg,
+                    _ => {
+                        println!("Dropping invalid mail from {}", 
+                                 mail.src_uuid);
+                        continue;
+                    }
+                };
+                if self.has_known_key(&msg).await 
+                    && self.accept_group_tag(&msg).await
+                    && self.queue_message(msg).await {
+                    posted += 1;
+                }
+            }
+
+            // invalid mail is acknowledged too, it won't get any better
+            let timestamp = request_timestamp();
+            let payload = AckMail::signing_payload(&self.uuid, &ids, 
+                                                   timestamp);
+            let request = RequestBody::AckMail(AckMail {
+                uuid: self.uuid,
+                ids,
+                timestamp,
+                signature: crypto::sign(&self.identity, &payload),
+            });
+            match self.server_request(request).await? {
+                ResponseBody::MailAcked { .. } => (),
+                other => return Err(unexpected_response(other)),
+            }
+
+            if remaining == 0 {
+                break;
+            }
+        }
+        Ok(posted)
+    }
+
+    /// fetches the mail and tells the user how much there was
+    pub(crate) async fn fetch_mail_command(&self) {
+        match self.fetch_mail().await {
+            Ok(0) => (),
+            Ok(posted) => println!("\x1b[1m{} messages were waiting in your \
+                                   mailbox\x1b[0m", posted),
+            Err(err) => println!("Error fetching mail: {}", err),
+        }
+    }
+}
@@ -6,27 +6,176 @@
  * Description: Main entrypoint for protocol run by client
  */
 pub mod client;
-pub mod message;
+pub mod contacts;
+pub mod demux;
+pub mod group;
+pub mod identity;
+pub mod mailbox;
+pub mod punch;
+pub mod relay;
+pub mod transfer;
 
 use client::Client;
-use tokio;
-use std::env;
+use contacts::ContactBook;
+use identity::Identity;
+use transfer::FileReceiver;
+use protocol::{Codec, DEFAULT_SERVER_PORT};
+use protocol::request::normalize_username;
+use protocol::net::default_listen_ip;
+use protocol::settings::Settings;
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process,
+    sync::Arc,
+};
+use tokio::{net::lookup_host, time::Duration};
+
+static USAGE: &str = "Try: ./client_protocol [--config <file>] \
+                     [--listen <ip>] [--port <port>] [--server <host:port>] \
+                     [--server_timeout <ms>] [--server_retries <count>] \
+                     [--codecs <codec,...>] [--username <name>] \
+                     [--max_message_size <bytes>] [--contacts_file <file>] \
+                     [--downloads_dir <dir>] [<port_number> [identity_file]]";
+
+/// the central index server's address. `server` is an `ip:port`, a
+/// `host:port` or a bare ip or host, which is on `DEFAULT_SERVER_PORT`.
+/// IPv6 addresses with a port are written `[ip]:port`
+async fn resolve_server(server: &str) -> Result<SocketAddr, String> {
+    if let Ok(ip) = server.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_SERVER_PORT));
+    }
+    let server = match server.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => server.to_string(),
+        _ => format!("{}:{}", server, DEFAULT_SERVER_PORT),
+    };
+    let addr = match lookup_host(&server).await {
+        Ok(mut addrs) => addrs.next(),
+        Err(err) => return Err(format!("Could not resolve {}: {}", server, 
+                                       err)),
+    };
+    addr.ok_or(format!("{} has no address", server))
+}
 
 /// calls the Client functions/methods
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
-    assert_eq!(args.len(), 2, "Try: ./client_protocol <port_number>");
-    
-    let port: u16 = args[1].parse()
-        .expect("Please enter a valid <port_number>");
+    let args: Vec<String> = env::args().skip(1).collect();
+    // each setting is a flag, e.g. `--server index.example.org`, else an
+    // environment variable, else a key of the file named by `--config` or
+    // `P2P_CONFIG`
+    let settings = match Settings::load(&args, 
+                                        &["listen", "port", "server", 
+                                          "server_timeout", 
+                                          "server_retries", "codecs",
+                                          "username", "max_message_size",
+                                          "contacts_file", "downloads_dir"],
+                                        "P2P_CONFIG") {
+        Ok(settings) => settings,
+        Err(err) => panic!("{}. {}", err, USAGE),
+    };
+    assert!(settings.positional.len() <= 2, "{}", USAGE);
+
+    // the port may still be given first on the command line
+    let port: u16 = match settings.positional.first() {
+        Some(port) => port.parse().expect("Please enter a valid <port_number>"),
+        None => settings.parse("port", "P2P_PORT")
+            .expect(USAGE)
+            .expect(USAGE),
+    };
+    // where the central index server is, `127.0.0.1:50000` by default
+    let server = settings.get("server", "P2P_SERVER_ADDR")
+        .unwrap_or(format!("127.0.0.1:{}", DEFAULT_SERVER_PORT));
+    let server_addr = match resolve_server(&server).await {
+        Ok(server_addr) => server_addr,
+        Err(err) => panic!("{}. {}", err, USAGE),
+    };
+    // every interface by default, so that peers on other hosts can reach us
+    // over either IPv4 or IPv6
+    let listen_ip: IpAddr = settings.parse("listen", "P2P_LISTEN")
+        .expect(USAGE)
+        .unwrap_or_else(default_listen_ip);
+
+    // one identity per port by default, so that several local clients don't
+    // end up sharing a UUID
+    let identity_path = match settings.positional.get(1) {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!("identity_{}.json", port)),
+    };
+    let identity = if identity_path.exists() {
+        Identity::load(&identity_path).unwrap()
+    } else {
+        Identity::generate()
+    };
 
     // clones the atomic reference counters, not the data. Underlying data is 
     // shared across threads.
-    let mut client_0 = Client::build(port).await.unwrap();
+    let listen_addr = SocketAddr::new(listen_ip, port);
+    let mut client_0 = Client::build_with_identity(listen_addr, identity)
+        .await
+        .unwrap();
+
+    client_0.server_addr = server_addr;
+
+    // how patiently the server is waited for, e.g. over a slow link
+    if let Some(server_timeout) = settings
+        .parse("server_timeout", "P2P_SERVER_TIMEOUT_MS")
+        .expect(USAGE) {
+        client_0.server_timeout = Duration::from_millis(server_timeout);
+    }
+    if let Some(server_retries) = settings
+        .parse("server_retries", "P2P_SERVER_RETRIES")
+        .expect(USAGE) {
+        client_0.server_retries = server_retries;
+    }
+
+    // e.g. `--codecs json` to keep peer traffic readable while debugging
+    if let Some(codecs) = settings.get("codecs", "P2P_CODECS") {
+        let codecs: Vec<Codec> = codecs.split(',')
+            .map(|codec| Codec::parse(codec.trim())
+                 .expect("codecs takes a list of `binary` and `json`"))
+            .collect();
+        assert!(!codecs.is_empty(), "codecs needs at least one codec");
+        client_0.codecs = codecs;
+    }
+
+    // a handle others can find us by, e.g. `--username alice` lets peers
+    // send to `@alice`
+    if let Some(username) = settings.get("username", "P2P_USERNAME") {
+        assert!(normalize_username(&username).is_some(), 
+                "username takes 3 to 32 letters, digits, `_`, `-` or `.`");
+        client_0.username = Some(username);
+    }
+
+    // largest message, in bytes, that is sent or accepted
+    if let Some(max_message_size) = settings
+        .parse("max_message_size", "P2P_MAX_MESSAGE_SIZE")
+        .expect("max_message_size must be a number of bytes") {
+        client_0.max_message_size = max_message_size;
+    }
+
+    // contacts are kept per port like the identity, unless configured
+    let contacts_path = match settings.get("contacts_file", 
+                                           "P2P_CONTACTS_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(format!("contacts_{}.json", port)),
+    };
+    *client_0.contacts.lock().await = ContactBook::load(&contacts_path)
+        .unwrap();
+
+    if let Some(downloads_dir) = settings.get("downloads_dir", 
+                                              "P2P_DOWNLOADS_DIR") {
+        client_0.file_receiver = Arc::new(FileReceiver::new(
+                client_0.uuid, PathBuf::from(downloads_dir)));
+    }
+
+    // the server answers on the listening socket, which has to be read
+    // before we ask it anything
+    let incoming = client_0.spawn_demux();
 
     // register with server, obtain UUID
-    let _ = match client_0.register_with_server().await {
+    match client_0.register_with_server().await {
         Ok(valid_uuid) => println!("your uuid is: {}", valid_uuid),
         Err(err) => {
             let err_msg = format!("Error getting UUID from server. {}", err);
@@ -34,12 +183,26 @@ async fn main() {
         }
     };
 
+    // lets the server see where our listening socket is reached from
+    if let Err(err) = client_0.send_punch(None).await {
+        println!("Error punching server. {}", err);
+    }
+
+    // messages left for us while we were offline
+    client_0.fetch_mail_command().await;
+
+    if let Err(err) = client_0.save_identity(&identity_path) {
+        println!("Error saving identity, next run gets a new UUID. {}", err);
+    }
+
+    let client = client_0.clone();
     let mut client_1 = client_0.clone();
     let mut client_2 = client_0.clone();
+    let mut client_3 = client_0.clone();
 
     let handles = vec![
         tokio::spawn(async move {
-            client_0.incoming_traff_loop().await;
+            client_0.incoming_traff_loop(incoming).await;
         }),
         tokio::spawn(async move {
             client_1.display_loop().await;
@@ -47,13 +210,30 @@ async fn main() {
         tokio::spawn(async move {
             client_2.outgoing_traff_loop().await;
         }),
+        tokio::spawn(async move {
+            client_3.heartbeat_loop().await;
+        }),
     ];
 
-    for handle in handles {
-        handle.await.unwrap();
+    // the loops run forever, so we only stop on Ctrl-C
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = async {
+            for handle in handles {
+                handle.await.unwrap();
+            }
+        } => {},
+    }
+
+    // leave the network so that peers stop being routed to our socket
+    println!();
+    match client.deregister_from_server().await {
+        Ok(_) => println!("deregistered from server, bye"),
+        Err(err) => println!("Error deregistering from server. {}", err),
     }
-    // so that we wait for execution (loops forever)
-    // handle.await.unwrap();
+
+    // the outgoing loop may be blocked reading stdin, don't wait for it
+    process::exit(0);
 }
 
 /* ===== SOME TEST CODE ======================================================*/
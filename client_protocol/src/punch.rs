@@ -0,0 +1,151 @@
+/*
+ * File: punch.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: NAT traversal. Keeping the server informed of where the
+ * listening socket is seen from, and punching holes towards other peers
+ */
+use std::net::SocketAddr;
+use tokio::{net::UdpSocket, time::{self, Duration}};
+use protocol::{punch, Punch, Request, RequestBody, Response, ResponseBody};
+use protocol::crypto;
+use uuid::Uuid;
+use crate::client::{
+    request_timestamp, unspecified_for, Client, ClientError, PeerEntry,
+};
+use crate::relay::decode_relayed;
+
+// probes sent towards a peer when punching a hole, and the time between them
+static PROBE_COUNT: usize = 5;
+static PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+impl Client {
+    /// the address registered with the server: the one of the interface the
+    /// server is reached through, with the listening port. Peers on the same
+    /// network can use it directly, the others need the observed address
+    pub(crate) async fn advertised_addr(&self) 
+        -> Result<SocketAddr, ClientError> {
+        if !self.listen_addr.ip().is_unspecified() {
+            return Ok(self.listen_addr);
+        }
+
+        // connecting a UDP socket sends nothing, it only picks the route
+        let local_addr = match UdpSocket::bind(unspecified_for(&self.server_addr))
+            .await {
+            Ok(socket) => socket.connect(self.server_addr).await
+                .and_then(|_| socket.local_addr()),
+            Err(err) => Err(err),
+        };
+        match local_addr {
+            Ok(local_addr) => Ok(SocketAddr::new(local_addr.ip(), 
+                                                 self.listen_addr.port())),
+            Err(err) => {
+                let err_msg = format!("No route to server. {}", err);
+                Err(ClientError::ServerUnavailableError(err_msg))
+            }
+        }
+    }
+
+    /// punches the server from the listening socket, so that it learns our
+    /// observed address. With a `target`, the server also has us and the
+    /// target punch holes towards each other. The server's answer arrives
+    /// on the listening socket, see handle_server_push()
+    ///
+    /// `target`: the peer to punch a hole towards, if any
+    pub async fn send_punch(&self, target: Option<Uuid>) 
+        -> Result<(), ClientError> {
+        let timestamp = request_timestamp();
+        let payload = Punch::signing_payload(&self.uuid, target.as_ref(), 
+                                             timestamp);
+        let request = Request::new(RequestBody::Punch(Punch {
+            uuid: self.uuid,
+            target,
+            timestamp,
+            signature: crypto::sign(&self.identity, &payload),
+        }));
+
+        match self.send_from_listener(&request.to_bytes(), 
+                                      self.server_addr).await {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let err_msg = format!("Unable to reach server. {}", err);
+                Err(ClientError::ServerUnavailableError(err_msg))
+            }
+        }
+    }
+
+    /// asks for a hole to be punched towards a peer that was just looked up,
+    /// if the server sees it at another address than the one it registered
+    ///
+    /// `peer_uuid`: the uuid of the peer
+    /// `peer_entry`: what the server told us about the peer
+    pub(crate) async fn punch_if_needed(&self, peer_uuid: &Uuid, 
+                                        peer_entry: &PeerEntry) {
+        if peer_entry.addrs().len() < 2 {
+            return;
+        }
+        if let Err(err) = self.send_punch(Some(*peer_uuid)).await {
+            println!("Error punching a hole towards {}: {}", peer_uuid, err);
+        }
+    }
+
+    /// handles a datagram the server sent to the listening socket, i.e. the
+    /// answer to a punch or a relayed datagram. Returns the relayed datagram
+    /// and the peer it comes from, for incoming_traff_loop() to handle
+    ///
+    /// `datagram`: the received datagram
+    pub(crate) fn handle_server_push(&self, datagram: &[u8]) 
+        -> Option<(Uuid, Vec<u8>)> {
+        let response = match Response::from_bytes(datagram) {
+            Ok(response) => response,
+            Err(code) => {
+                println!("Invalid datagram from server: {}", code);
+                return None;
+            }
+        };
+
+        match response.body {
+            ResponseBody::Punch { uuid, address } => {
+                println!("Punching a hole towards {} at {}", uuid, address);
+                self.spawn_probes(address);
+            },
+            ResponseBody::PeerMoved { uuid, address, observed_address } => {
+                // we aren't async, the move is taken in by a task of its own
+                let client = self.clone();
+                tokio::spawn(async move {
+                    client.peer_moved(&uuid, address, observed_address).await;
+                });
+            },
+            // we don't need to know our observed address, only the server
+            ResponseBody::Observed { .. } => (),
+            ResponseBody::Relayed { uuid, datagram } => 
+                match decode_relayed(&datagram) {
+                    Some(datagram) => return Some((uuid, datagram)),
+                    None => println!("Invalid datagram relayed from {}", uuid),
+                },
+            ResponseBody::Error { code, message } => 
+                println!("\x1b[31mServer refused request: {} ({})\x1b[0m", 
+                         message, code),
+            other => println!("Unexpected datagram from server: {:?}", other),
+        }
+        None
+    }
+
+    /// sends probes from the listening socket to `addr`, which opens our NAT
+    /// to traffic coming back from there
+    fn spawn_probes(&self, addr: SocketAddr) {
+        let client = self.clone();
+        let probe = punch::probe(&self.uuid);
+        tokio::spawn(async move {
+            for _ in 0..PROBE_COUNT {
+                if let Err(err) = client.send_from_listener(&probe, addr)
+                    .await {
+                    println!("Error probing {}: {}", addr, err);
+                    return;
+                }
+                time::sleep(PROBE_INTERVAL).await;
+            }
+        });
+    }
+}
@@ -0,0 +1,43 @@
+/*
+ * File: relay.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: sending datagrams to peers that can't be reached directly
+ * through the server's relay, and receiving the ones relayed to us
+ */
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use protocol::{Relay, Request, RequestBody};
+use uuid::Uuid;
+use crate::client::{request_timestamp, Client, ClientError};
+
+impl Client {
+    /// asks the server to forward a datagram to a peer. The datagram is 
+    /// encrypted for the peer already, so the server can't read it
+    ///
+    /// `peer_uuid`: the uuid of the recipient
+    /// `datagram`: an encoded message or fragment
+    pub async fn send_relayed(&self, peer_uuid: &Uuid, datagram: &[u8]) 
+        -> Result<(), ClientError> {
+        let request = Request::new(RequestBody::Relay(Relay::new(
+                    self.uuid, *peer_uuid, datagram, request_timestamp(), 
+                    &self.identity)));
+
+        match self.send_from_listener(&request.to_bytes(), 
+                                      self.server_addr).await {
+            Ok(_) => Ok(()),
+            Err(err) => {
+                let err_msg = format!("Unable to reach relay. {}", err);
+                Err(ClientError::ServerUnavailableError(err_msg))
+            }
+        }
+    }
+}
+
+/// the datagram carried by a `relayed` push from the server, `None` if it
+/// isn't valid base64
+///
+/// `datagram`: the base64 encoded datagram
+pub fn decode_relayed(datagram: &str) -> Option<Vec<u8>> {
+    BASE64.decode(datagram).ok()
+}
@@ -0,0 +1,685 @@
+/*
+ * File: transfer.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: sending files to peers, and receiving files from them into
+ * the downloads directory
+ */
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    io::{Read, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+    task::{self, JoinSet},
+};
+use uuid::Uuid;
+use protocol::message::{Message, MessageType};
+use protocol::transfer::{
+    self, FileChunk, FileEnd, FileOffer, FileReply, CHUNK_SIZE,
+};
+use crate::client::{Client, ClientError, PeerEntry};
+
+/// chunks in flight at once. The sender only moves on once the receiver has
+/// acknowledged a chunk, i.e. written it to disk, so a slow receiver slows
+/// the sender down
+static WINDOW_SIZE: usize = 8;
+/// completed transfers whose outcome is remembered. The oldest make room for
+/// new ones, so that many small files can't grow the receiver without bound
+static MAX_FINISHED: usize = 1024;
+
+impl Client {
+    /// sends a file to a peer, in chunks of `CHUNK_SIZE` with at most
+    /// `WINDOW_SIZE` of them unacknowledged. If an earlier transfer of the
+    /// same file to the same peer was interrupted, only the chunks the peer
+    /// doesn't have yet are sent. The peer checks the file against its
+    /// SHA-256 once it has every chunk.
+    ///
+    /// `peer_uuid`: the uuid of the recipient
+    /// `path`: the file to send
+    pub async fn send_file(&self, peer_uuid: &Uuid, path: &Path)
+        -> Result<(), ClientError> {
+        let peer_entry = self.resolve_peer(peer_uuid).await?;
+
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => return Err(ClientError::FileTransferError(
+                    format!("{} is not a file", path.display()))),
+        };
+        // hashing reads the whole file, which mustn't tie up a worker
+        let hashed = {
+            let path = path.to_path_buf();
+            task::spawn_blocking(move || hash_file(&path)).await
+        };
+        let (size, sha256) = match hashed {
+            Ok(Ok(hashed)) => hashed,
+            Ok(Err(err)) => return Err(ClientError::FileTransferError(
+                    format!("Could not read {}: {}", path.display(), err))),
+            Err(err) => 
+                return Err(ClientError::FileTransferError(err.to_string())),
+        };
+
+        let offer = FileOffer {
+            transfer_id: transfer::transfer_id(&sha256, &self.uuid, peer_uuid),
+            name,
+            size,
+            sha256,
+            chunk_size: CHUNK_SIZE,
+        };
+        let resume_from = match self.send_file_message(
+            &peer_entry, peer_uuid, MessageType::FileOffer,
+            &transfer::to_data(&offer)).await? {
+            FileReply::Accepted { resume_from } => resume_from,
+            reply => return Err(refused(reply)),
+        };
+
+        let chunk_count = offer.chunk_count();
+        if resume_from > 0 {
+            println!("Resuming {} at chunk {}/{}", offer.name, resume_from,
+                     chunk_count);
+        }
+
+        let mut file = File::open(path).await.map_err(|err|
+            ClientError::FileTransferError(err.to_string()))?;
+        let mut in_flight = JoinSet::new();
+        let mut next_index = resume_from;
+        let mut stored = resume_from;
+        while stored < chunk_count {
+            // keep the window full
+            while next_index < chunk_count && in_flight.len() < WINDOW_SIZE {
+                let bytes = read_chunk(&mut file, &offer, next_index).await
+                    .map_err(|err|
+                             ClientError::FileTransferError(err.to_string()))?;
+                let chunk = FileChunk::new(offer.transfer_id, next_index,
+                                           &bytes);
+                let client = self.clone();
+                let peer_uuid = *peer_uuid;
+                in_flight.spawn(async move {
+                    client.send_file_message(&peer_entry, &peer_uuid,
+                                             MessageType::FileChunk,
+                                             &transfer::to_data(&chunk)).await
+                });
+                next_index += 1;
+            }
+
+            match in_flight.join_next().await {
+                Some(Ok(Ok(FileReply::Stored))) => stored += 1,
+                Some(Ok(Ok(reply))) => return Err(refused(reply)),
+                Some(Ok(Err(err))) => {
+                    let err_msg = format!("Transfer of {} interrupted at \
+                                          {}/{} chunks, send it again to \
+                                          resume. {}", offer.name, stored,
+                                          chunk_count, err);
+                    return Err(ClientError::FileTransferError(err_msg));
+                },
+                Some(Err(err)) =>
+                    return Err(ClientError::FileTransferError(err.to_string())),
+                None => break,
+            }
+
+            // report progress every tenth of the file
+            if stored * 10 / chunk_count != (stored - 1) * 10 / chunk_count {
+                println!("{}: {}%", offer.name, stored * 100 / chunk_count);
+            }
+        }
+
+        let end = FileEnd { transfer_id: offer.transfer_id };
+        match self.send_file_message(&peer_entry, peer_uuid,
+                                     MessageType::FileEnd,
+                                     &transfer::to_data(&end)).await? {
+            FileReply::Verified => Ok(()),
+            reply => Err(refused(reply)),
+        }
+    }
+
+    /// delivers one message of a file transfer and returns the peer's reply
+    async fn send_file_message(&self, peer_entry: &PeerEntry, peer_uuid: &Uuid,
+                               msg_type: MessageType, data: &str)
+        -> Result<FileReply, ClientError> {
+        let mut msg = Message::new(*peer_uuid, self.uuid, data).map_err(|err|
+            ClientError::FileTransferError(err.to_string()))?;
+        msg.msg_type = msg_type;
+
+        let ack = match self.deliver(peer_entry, &msg).await? {
+            Some((ack, _)) => ack,
+            None => return Err(ClientError::FileTransferError(
+                    "Peer did not acknowledge".to_string())),
+        };
+        match transfer::from_data(&ack.data) {
+            Some(reply) => Ok(reply),
+            None => Err(ClientError::FileTransferError(
+                    "Invalid reply from peer".to_string())),
+        }
+    }
+}
+
+/// a file being received, stored as `.<transfer_id>.part` in the downloads
+/// directory until it is complete
+struct IncomingFile {
+    offer: FileOffer,
+    src_uuid: Uuid,
+    /// locked while a chunk is written to it, or while it is verified
+    part: Arc<Mutex<File>>,
+    /// number of leading chunks that are all on disk
+    contiguous: u64,
+    /// indices of the chunks on disk past `contiguous`
+    stored: BTreeSet<u64>,
+}
+
+impl IncomingFile {
+    /// records chunk `index` as on disk. Returns whether `contiguous` grew
+    fn mark_stored(&mut self, index: u64) -> bool {
+        if index < self.contiguous || !self.stored.insert(index) {
+            return false;
+        }
+        let contiguous = self.contiguous;
+        while self.stored.remove(&self.contiguous) {
+            self.contiguous += 1;
+        }
+        self.contiguous != contiguous
+    }
+
+    fn is_stored(&self, index: u64) -> bool {
+        index < self.contiguous || self.stored.contains(&index)
+    }
+}
+
+/// receives the files sent by peers. Every transfer is resumable: the part
+/// file and a `.<transfer_id>.progress` file, recording the offer and how many
+/// leading chunks are on disk, survive a restart of either side
+///
+/// Shared by the tasks handling received messages. The transfers are only
+/// locked to look them up and to record progress, never while a chunk is
+/// written or a file is hashed, so that transfers don't hold each other up
+pub struct FileReceiver {
+    /// the client's own UUID, which every transfer_id is derived from
+    uuid: Uuid,
+    pub downloads_dir: PathBuf,
+    transfers: Mutex<Transfers>,
+}
+
+/// the transfers under way, and those completed
+#[derive(Default)]
+struct Transfers {
+    incoming: HashMap<Uuid, IncomingFile>,
+    /// outcome of completed transfers, for answering retransmitted ends
+    finished: HashMap<(Uuid, Uuid), FileReply>,
+    finished_order: VecDeque<(Uuid, Uuid)>,  // keys of `finished`, oldest first
+}
+
+impl Transfers {
+    /// records the outcome of the transfer `key`, forgetting the oldest
+    /// outcome past `MAX_FINISHED`
+    fn finish(&mut self, key: (Uuid, Uuid), reply: FileReply) {
+        if self.finished.insert(key, reply).is_some() {
+            return;
+        }
+        self.finished_order.push_back(key);
+        while self.finished_order.len() > MAX_FINISHED {
+            if let Some(oldest) = self.finished_order.pop_front() {
+                self.finished.remove(&oldest);
+            }
+        }
+    }
+
+    /// forgets the outcome of the transfer `key`
+    fn unfinish(&mut self, key: &(Uuid, Uuid)) {
+        if self.finished.remove(key).is_some() {
+            self.finished_order.retain(|finished| finished != key);
+        }
+    }
+}
+
+impl FileReceiver {
+    /// `uuid`: the client's own UUID
+    /// `downloads_dir`: where received files are saved, created on demand
+    pub fn new(uuid: Uuid, downloads_dir: PathBuf) -> FileReceiver {
+        FileReceiver {
+            uuid,
+            downloads_dir,
+            transfers: Mutex::new(Transfers::default()),
+        }
+    }
+
+    /// handles a received `File*` message, returning the reply sent back in
+    /// its ack. Retransmitted messages get the same reply again
+    pub async fn handle(&self, msg: &Message) -> FileReply {
+        let reply = match msg.msg_type {
+            MessageType::FileOffer => match transfer::from_data(&msg.data) {
+                Some(offer) => 
+                    Some(self.handle_offer(msg.src_uuid, offer).await),
+                None => None,
+            },
+            MessageType::FileChunk => match transfer::from_data(&msg.data) {
+                Some(chunk) => 
+                    Some(self.handle_chunk(msg.src_uuid, chunk).await),
+                None => None,
+            },
+            MessageType::FileEnd => match transfer::from_data(&msg.data) {
+                Some(end) => Some(self.handle_end(msg.src_uuid, end).await),
+                None => None,
+            },
+            _ => None,
+        };
+        match reply {
+            Some(Ok(reply)) => reply,
+            Some(Err(reason)) => FileReply::Rejected { reason },
+            None => FileReply::Rejected {
+                reason: "malformed file message".to_string()
+            },
+        }
+    }
+
+    /// opens, or reopens, the part file of an offered transfer and tells the
+    /// sender how many chunks it can skip. Offers are few, so the transfers
+    /// stay locked until the part file is open, and a retransmitted offer
+    /// can't open it a second time
+    async fn handle_offer(&self, src_uuid: Uuid, offer: FileOffer)
+        -> Result<FileReply, String> {
+        let mut transfers = self.transfers.lock().await;
+        if let Some(incoming) = transfers.incoming.get(&offer.transfer_id) {
+            if incoming.src_uuid != src_uuid || incoming.offer != offer {
+                return Err("conflicting offer".to_string());
+            }
+            return Ok(FileReply::Accepted {
+                resume_from: incoming.contiguous
+            });
+        }
+
+        if offer.chunk_size == 0 || sanitize_name(&offer.name).is_none() {
+            return Err("invalid offer".to_string());
+        }
+        // the id names the part file, so a sender mustn't pick one that
+        // belongs to somebody else's transfer
+        let transfer_id = transfer::transfer_id(&offer.sha256, &src_uuid,
+                                                &self.uuid);
+        if offer.transfer_id != transfer_id {
+            return Err("invalid transfer id".to_string());
+        }
+
+        fs::create_dir_all(&self.downloads_dir).await
+            .map_err(|err| err.to_string())?;
+        let part_path = self.part_path(&offer.transfer_id);
+        let progress_path = self.progress_path(&offer.transfer_id);
+
+        // a transfer interrupted earlier keeps the chunks it got
+        let saved: Option<Progress> = fs::read_to_string(&progress_path).await
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok());
+        let part_exists = fs::try_exists(&part_path).await.unwrap_or(false);
+        let resume_from = match saved {
+            Some(saved) if saved.offer == offer && part_exists => 
+                saved.contiguous,
+            _ => 0,
+        };
+
+        let part = OpenOptions::new().create(true).truncate(resume_from == 0)
+            .read(true).write(true).open(&part_path).await
+            .map_err(|err| err.to_string())?;
+
+        // the same file sent again after an earlier transfer completed
+        transfers.unfinish(&(src_uuid, offer.transfer_id));
+
+        self.save_progress(&offer, resume_from).await?;
+        println!("\x1b[1mReceiving\x1b[0m {} ({}B) from \x1b[36m{}\x1b[0m",
+                 offer.name, offer.size, src_uuid);
+        let incoming = IncomingFile {
+            contiguous: resume_from,
+            stored: BTreeSet::new(),
+            offer,
+            src_uuid,
+            part: Arc::new(Mutex::new(part)),
+        };
+        transfers.incoming.insert(incoming.offer.transfer_id, incoming);
+        Ok(FileReply::Accepted { resume_from })
+    }
+
+    /// writes a chunk to its place in the part file
+    async fn handle_chunk(&self, src_uuid: Uuid, chunk: FileChunk)
+        -> Result<FileReply, String> {
+        let bytes = chunk.bytes().ok_or("invalid chunk data")?;
+        let (part, offset) = {
+            let transfers = self.transfers.lock().await;
+            let incoming = match transfers.incoming.get(&chunk.transfer_id) {
+                Some(incoming) if incoming.src_uuid == src_uuid => incoming,
+                _ => return Err("unknown transfer".to_string()),
+            };
+            if chunk.index >= incoming.offer.chunk_count()
+                || bytes.len() as u64 
+                    != incoming.offer.chunk_len(chunk.index) {
+                return Err("invalid chunk".to_string());
+            }
+            if incoming.is_stored(chunk.index) {
+                return Ok(FileReply::Stored);
+            }
+            (Arc::clone(&incoming.part), 
+             chunk.index * incoming.offer.chunk_size)
+        };
+
+        {
+            let mut part = part.lock().await;
+            part.seek(SeekFrom::Start(offset)).await
+                .map_err(|err| err.to_string())?;
+            part.write_all(&bytes).await.map_err(|err| err.to_string())?;
+            // the write only completes in the background until flushed
+            part.flush().await.map_err(|err| err.to_string())?;
+        }
+
+        // only the contiguous prefix is recorded, so only then is there
+        // progress worth saving. Saved under the lock, so that an older
+        // prefix can't overwrite a newer one
+        let transfers = &mut *self.transfers.lock().await;
+        if let Some(incoming) = transfers.incoming.get_mut(&chunk.transfer_id) {
+            if incoming.mark_stored(chunk.index) {
+                self.save_progress(&incoming.offer, incoming.contiguous)
+                    .await?;
+            }
+        }
+        Ok(FileReply::Stored)
+    }
+
+    /// checks a complete file against its hash, and moves it into the
+    /// downloads directory if it matches. The part file stays locked until
+    /// the outcome is recorded, so a retransmitted end waits for it instead
+    /// of hashing the file again
+    async fn handle_end(&self, src_uuid: Uuid, end: FileEnd)
+        -> Result<FileReply, String> {
+        let key = (src_uuid, end.transfer_id);
+        let part = {
+            let transfers = self.transfers.lock().await;
+            // the retransmission of an end that was already handled
+            if let Some(reply) = transfers.finished.get(&key) {
+                return Ok(reply.clone());
+            }
+            let incoming = match transfers.incoming.get(&end.transfer_id) {
+                Some(incoming) if incoming.src_uuid == src_uuid => incoming,
+                _ => return Err("unknown transfer".to_string()),
+            };
+            let contiguous = incoming.contiguous;
+            if contiguous < incoming.offer.chunk_count() {
+                return Ok(FileReply::Incomplete { missing: contiguous });
+            }
+            Arc::clone(&incoming.part)
+        };
+
+        let _part = part.lock().await;
+        let offer = {
+            let transfers = self.transfers.lock().await;
+            // handled by another copy of the end while we waited
+            if let Some(reply) = transfers.finished.get(&key) {
+                return Ok(reply.clone());
+            }
+            match transfers.incoming.get(&end.transfer_id) {
+                Some(incoming) => incoming.offer.clone(),
+                None => return Err("unknown transfer".to_string()),
+            }
+        };
+
+        // hashing reads the whole file, which mustn't tie up a worker
+        let part_path = self.part_path(&end.transfer_id);
+        let hashed = {
+            let part_path = part_path.clone();
+            task::spawn_blocking(move || hash_file(&part_path)).await
+        };
+        let sha256 = match hashed {
+            Ok(Ok((_, sha256))) => sha256,
+            Ok(Err(err)) => return Err(err.to_string()),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let _ = fs::remove_file(self.progress_path(&end.transfer_id)).await;
+        let reply = if sha256 == offer.sha256 {
+            let file_path = self.unused_path(&offer.name).await;
+            fs::rename(&part_path, &file_path).await
+                .map_err(|err| err.to_string())?;
+            println!("\x1b[1mReceived\x1b[0m {} from \x1b[36m{}\x1b[0m, \
+                     saved to {}", offer.name, src_uuid, 
+                     file_path.display());
+            FileReply::Verified
+        } else {
+            let _ = fs::remove_file(&part_path).await;
+            println!("\x1b[31mDiscarded {} from {}: hash mismatch\x1b[0m",
+                     offer.name, src_uuid);
+            FileReply::Corrupt
+        };
+
+        let mut transfers = self.transfers.lock().await;
+        transfers.incoming.remove(&end.transfer_id);
+        transfers.finish(key, reply.clone());
+        Ok(reply)
+    }
+
+    fn part_path(&self, transfer_id: &Uuid) -> PathBuf {
+        self.downloads_dir.join(format!(".{}.part", transfer_id))
+    }
+
+    fn progress_path(&self, transfer_id: &Uuid) -> PathBuf {
+        self.downloads_dir.join(format!(".{}.progress", transfer_id))
+    }
+
+    /// where to save a received file without overwriting an existing one:
+    /// `name`, else `name (1)`, `name (2)`, ...
+    async fn unused_path(&self, name: &str) -> PathBuf {
+        let name = sanitize_name(name).unwrap();
+        let mut path = self.downloads_dir.join(&name);
+        let mut copy = 1;
+        while fs::try_exists(&path).await.unwrap_or(false) {
+            path = self.downloads_dir.join(format!("{} ({})", name, copy));
+            copy += 1;
+        }
+        path
+    }
+
+    /// records the offer and the contiguous chunks on disk, for resuming
+    async fn save_progress(&self, offer: &FileOffer, contiguous: u64) 
+        -> Result<(), String> {
+        let progress = Progress { offer: offer.clone(), contiguous };
+        fs::write(self.progress_path(&offer.transfer_id),
+                  transfer::to_data(&progress)).await
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// contents of a progress file
+#[derive(Serialize, Deserialize)]
+struct Progress {
+    offer: FileOffer,
+    contiguous: u64,
+}
+
+/// refuses peer-chosen names that could escape the downloads directory, or
+/// hide in it. Names with any directory in them are refused, not guessed at
+fn sanitize_name(name: &str) -> Option<String> {
+    let file_name = Path::new(name).file_name()?.to_str()?;
+    if file_name != name || name.contains(['/', '\\']) 
+        || name.starts_with('.') {
+        return None;
+    }
+    Some(name.to_string())
+}
+
+/// the size and hex encoded SHA-256 of a file. Blocks, so it is run with
+/// spawn_blocking()
+fn hash_file(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+    let mut size = 0;
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// reads chunk `index` of the offered file
+async fn read_chunk(file: &mut File, offer: &FileOffer, index: u64)
+    -> std::io::Result<Vec<u8>> {
+    let mut bytes = vec![0u8; offer.chunk_len(index) as usize];
+    file.seek(SeekFrom::Start(index * offer.chunk_size)).await?;
+    file.read_exact(&mut bytes).await?;
+    Ok(bytes)
+}
+
+/// the error for a reply that ends the transfer
+fn refused(reply: FileReply) -> ClientError {
+    let err_msg = match reply {
+        FileReply::Corrupt =>
+            "Peer received a corrupted file and discarded it".to_string(),
+        FileReply::Incomplete { missing } =>
+            format!("Peer is missing chunk {}", missing),
+        FileReply::Rejected { reason } =>
+            format!("Peer rejected the transfer: {}", reason),
+        reply => format!("Unexpected reply from peer: {:?}", reply),
+    };
+    ClientError::FileTransferError(err_msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a downloads directory of its own for every test
+    fn downloads_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("p2p_downloads_{}", Uuid::new_v4()))
+    }
+
+    fn contents() -> Vec<u8> {
+        (0..3 * CHUNK_SIZE + 5).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn offer_for(contents: &[u8], src_uuid: &Uuid, dst_uuid: &Uuid)
+        -> FileOffer {
+        let sha256 = hex::encode(Sha256::digest(contents));
+        FileOffer {
+            transfer_id: transfer::transfer_id(&sha256, src_uuid, dst_uuid),
+            name: "file.bin".to_string(),
+            size: contents.len() as u64,
+            sha256,
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    fn chunk(offer: &FileOffer, contents: &[u8], index: u64) -> FileChunk {
+        let start = (index * offer.chunk_size) as usize;
+        let end = start + offer.chunk_len(index) as usize;
+        FileChunk::new(offer.transfer_id, index, &contents[start..end])
+    }
+
+    fn end(offer: &FileOffer) -> FileEnd {
+        FileEnd { transfer_id: offer.transfer_id }
+    }
+
+    #[tokio::test]
+    async fn transfer_resumes_after_a_restart() {
+        let (dir, contents, src_uuid, dst_uuid) = 
+            (downloads_dir(), contents(), Uuid::new_v4(), Uuid::new_v4());
+        let offer = offer_for(&contents, &src_uuid, &dst_uuid);
+
+        let receiver = FileReceiver::new(dst_uuid, dir.clone());
+        assert_eq!(receiver.handle_offer(src_uuid, offer.clone()).await,
+                   Ok(FileReply::Accepted { resume_from: 0 }));
+        for index in [0, 1, 3] {
+            assert_eq!(receiver.handle_chunk(
+                    src_uuid, chunk(&offer, &contents, index)).await,
+                Ok(FileReply::Stored));
+        }
+        drop(receiver);
+
+        // only the leading chunks on disk count, chunk 3 comes again
+        let receiver = FileReceiver::new(dst_uuid, dir.clone());
+        assert_eq!(receiver.handle_offer(src_uuid, offer.clone()).await,
+                   Ok(FileReply::Accepted { resume_from: 2 }));
+        assert_eq!(receiver.handle_end(src_uuid, end(&offer)).await,
+                   Ok(FileReply::Incomplete { missing: 2 }));
+        for index in [2, 3] {
+            receiver.handle_chunk(src_uuid, chunk(&offer, &contents, index))
+                .await.unwrap();
+        }
+        assert_eq!(receiver.handle_end(src_uuid, end(&offer)).await,
+                   Ok(FileReply::Verified));
+        assert_eq!(std::fs::read(dir.join("file.bin")).unwrap(), contents);
+        // a retransmitted end gets the same answer
+        assert_eq!(receiver.handle_end(src_uuid, end(&offer)).await,
+                   Ok(FileReply::Verified));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_with_the_wrong_hash_is_discarded() {
+        let (dir, contents, src_uuid, dst_uuid) = 
+            (downloads_dir(), contents(), Uuid::new_v4(), Uuid::new_v4());
+        let offer = offer_for(b"something else", &src_uuid, &dst_uuid);
+        let offer = FileOffer { size: contents.len() as u64, ..offer };
+
+        let receiver = FileReceiver::new(dst_uuid, dir.clone());
+        receiver.handle_offer(src_uuid, offer.clone()).await.unwrap();
+        for index in 0..offer.chunk_count() {
+            receiver.handle_chunk(src_uuid, chunk(&offer, &contents, index))
+                .await.unwrap();
+        }
+        assert_eq!(receiver.handle_end(src_uuid, end(&offer)).await,
+                   Ok(FileReply::Corrupt));
+        // neither the file nor its part and progress files are left
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oldest_outcomes_are_forgotten() {
+        let mut transfers = Transfers::default();
+        let keys: Vec<_> = (0..=MAX_FINISHED)
+            .map(|_| (Uuid::new_v4(), Uuid::new_v4()))
+            .collect();
+        for key in &keys {
+            transfers.finish(*key, FileReply::Verified);
+        }
+        assert_eq!(transfers.finished.len(), MAX_FINISHED);
+        assert!(!transfers.finished.contains_key(&keys[0]));
+        assert!(transfers.finished.contains_key(&keys[MAX_FINISHED]));
+
+        transfers.unfinish(&keys[1]);
+        assert_eq!(transfers.finished_order.len(), MAX_FINISHED - 1);
+    }
+
+    #[tokio::test]
+    async fn names_with_directories_are_refused() {
+        for name in ["../file.bin", "a/../../file.bin", "/etc/passwd", 
+                     "dir/file.bin", "..\\file.bin", "..", ".hidden", ""] {
+            assert_eq!(sanitize_name(name), None, "{}", name);
+        }
+        assert_eq!(sanitize_name("file (1).bin"), 
+                   Some("file (1).bin".to_string()));
+
+        let (src_uuid, dst_uuid) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut offer = offer_for(&contents(), &src_uuid, &dst_uuid);
+        offer.name = "../file.bin".to_string();
+        let receiver = FileReceiver::new(dst_uuid, downloads_dir());
+        assert!(receiver.handle_offer(src_uuid, offer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn offers_under_another_transfer_id_are_refused() {
+        let (dir, contents, src_uuid, dst_uuid) = 
+            (downloads_dir(), contents(), Uuid::new_v4(), Uuid::new_v4());
+        let receiver = FileReceiver::new(dst_uuid, dir.clone());
+        // meant for another recipient, or from another sender
+        for offer in [offer_for(&contents, &src_uuid, &Uuid::new_v4()),
+                      offer_for(&contents, &Uuid::new_v4(), &dst_uuid)] {
+            assert_eq!(receiver.handle_offer(src_uuid, offer).await,
+                       Err("invalid transfer id".to_string()));
+        }
+        assert!(!dir.exists());
+    }
+}
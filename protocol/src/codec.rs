@@ -0,0 +1,71 @@
+/*
+ * File: codec.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: the wire formats peer messages can be encoded with, and how
+ * two peers agree on one
+ */
+use serde::{Deserialize, Serialize};
+
+/// first byte of every binary frame. Can't be confused with the `{` that
+/// starts a json message, which is how receivers tell the two apart
+pub const BINARY_MAGIC: u8 = 0xb1;
+
+/// how a `Message` is laid out on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    /// human readable, spoken by every peer. Kept as fallback and for
+    /// debugging
+    Json,
+    /// compact length-prefixed frames, see `Message::to_binary()`
+    Binary,
+}
+
+/// the codecs this build can speak, in order of preference
+pub static SUPPORTED_CODECS: [Codec; 2] = [Codec::Binary, Codec::Json];
+
+impl Codec {
+    /// the value used for this codec in config and on the wire
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::Binary => "binary",
+        }
+    }
+
+    /// parses the value of `as_str()`
+    pub fn parse(codec: &str) -> Option<Codec> {
+        match codec {
+            "json" => Some(Codec::Json),
+            "binary" => Some(Codec::Binary),
+            _ => None,
+        }
+    }
+
+    /// the codec a received datagram was encoded with, going by its first
+    /// byte. `None` if it is neither
+    pub fn detect(bytes: &[u8]) -> Option<Codec> {
+        match bytes.first() {
+            Some(b'{') => Some(Codec::Json),
+            Some(&BINARY_MAGIC) => Some(Codec::Binary),
+            _ => None,
+        }
+    }
+
+    /// picks the codec for talking to a peer: the first of `ours` that the
+    /// peer also advertised in `theirs`. Falls back to json, which every peer
+    /// understands
+    pub fn negotiate(ours: &[Codec], theirs: &[Codec]) -> Codec {
+        ours.iter()
+            .find(|codec| theirs.contains(codec))
+            .copied()
+            .unwrap_or(Codec::Json)
+    }
+}
+
+/// what peers that don't advertise any codecs are assumed to speak
+pub fn default_codecs() -> Vec<Codec> {
+    vec![Codec::Json]
+}
@@ -0,0 +1,115 @@
+/*
+ * File: crypto.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: long-term key material of a client, end-to-end encryption
+ * of message payloads between two peers and signing of server requests
+ */
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use ed25519_dalek::{Signer, SigningKey};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// length of an AEAD nonce in bytes
+pub static NONCE_LEN: usize = 12;
+// domain separation for the key derived from the Diffie-Hellman secret
+static KEY_DERIVATION_CONTEXT: &[u8] = b"basic-p2p-messaging message key";
+
+/// a client's long-term x25519 keypair. The public half is published through
+/// the central index server, the secret half never leaves the client.
+#[derive(Clone)]
+pub struct KeyPair {
+    secret: StaticSecret,
+    pub public: PublicKey,
+}
+
+impl KeyPair {
+    /// generates a fresh random keypair
+    pub fn generate() -> KeyPair {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+        KeyPair { secret, public }
+    }
+
+    /// restores a keypair from its secret half
+    pub fn from_secret_bytes(secret_bytes: [u8; 32]) -> KeyPair {
+        let secret = StaticSecret::from(secret_bytes);
+        let public = PublicKey::from(&secret);
+        KeyPair { secret, public }
+    }
+
+    /// the secret half of the keypair, for persisting it
+    pub fn secret_bytes(&self) -> [u8; 32] {
+        self.secret.to_bytes()
+    }
+
+    /// hex representation of the public key, as sent over the wire
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.public.as_bytes())
+    }
+
+    /// encrypts `plaintext` so that only the holder of `peer_public`'s secret
+    /// key can read it. `aad` is authenticated but not encrypted.
+    ///
+    /// returns `(nonce, ciphertext)`
+    pub fn seal(&self, peer_public: &PublicKey, aad: &[u8], plaintext: &[u8])
+        -> (Vec<u8>, Vec<u8>) {
+        let cipher = self.cipher(peer_public);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        // encryption only fails if the plaintext exceeds the AEAD limits
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .expect("plaintext too large to encrypt");
+        (nonce.to_vec(), ciphertext)
+    }
+
+    /// decrypts and authenticates a payload sealed by `peer_public`'s holder.
+    /// Returns `None` if the ciphertext or `aad` were tampered with, or if
+    /// the payload was not meant for this keypair.
+    pub fn open(&self, peer_public: &PublicKey, nonce: &[u8], aad: &[u8],
+                ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if nonce.len() != NONCE_LEN {
+            return None;
+        }
+        let cipher = self.cipher(peer_public);
+        cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .ok()
+    }
+
+    /// builds the AEAD cipher for the channel between `self` and `peer_public`
+    fn cipher(&self, peer_public: &PublicKey) -> ChaCha20Poly1305 {
+        let shared_secret = self.secret.diffie_hellman(peer_public);
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_DERIVATION_CONTEXT);
+        hasher.update(shared_secret.as_bytes());
+        let key = hasher.finalize();
+        ChaCha20Poly1305::new(Key::from_slice(&key))
+    }
+}
+
+/// parses the hex representation of a public key
+pub fn parse_public_key(public_key_hex: &str) -> Option<PublicKey> {
+    let bytes: [u8; 32] = hex::decode(public_key_hex).ok()?.try_into().ok()?;
+    Some(PublicKey::from(bytes))
+}
+
+/// generates a fresh ed25519 identity key. The server derives the client's
+/// UUID from its public half
+pub fn generate_identity() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// hex representation of an identity's public (verifying) key
+pub fn verifying_key_hex(identity: &SigningKey) -> String {
+    hex::encode(identity.verifying_key().as_bytes())
+}
+
+/// signs `payload` with `identity`, returning the hex encoded signature
+pub fn sign(identity: &SigningKey, payload: &str) -> String {
+    hex::encode(identity.sign(payload.as_bytes()).to_bytes())
+}
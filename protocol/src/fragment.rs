@@ -0,0 +1,326 @@
+/*
+ * File: fragment.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: splitting encoded messages that don't fit in a single
+ * datagram into numbered fragments, and putting them back together
+ */
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// first byte of every fragment. Distinct from the first byte of both codecs,
+/// so receivers can tell a fragment from a whole message
+pub const FRAGMENT_MAGIC: u8 = 0xf7;
+/// largest datagram sent to a peer. Stays below common path MTUs so that
+/// datagrams aren't fragmented by IP, where a single lost piece loses it all
+pub const MAX_DATAGRAM_LEN: usize = 1200;
+/// largest datagram that UDP can carry, used to size receive buffers
+pub const MAX_UDP_PAYLOAD: usize = 65_507;
+/// magic, frame id, index and count
+const FRAGMENT_HEADER_LEN: usize = 1 + 16 + 2 + 2;
+/// bytes of the encoded message carried by each fragment
+const FRAGMENT_PAYLOAD_LEN: usize = MAX_DATAGRAM_LEN - FRAGMENT_HEADER_LEN;
+/// messages a single source may have waiting for fragments. A file transfer
+/// keeps 8 chunks in flight, next to the odd message
+pub const MAX_PARTIALS_PER_SOURCE: usize = 16;
+/// messages waiting for fragments, from every source together. Each may hold
+/// up to the largest message accepted
+pub const MAX_PARTIALS: usize = 128;
+
+/// splits an encoded message into the datagrams to send. A message that fits
+/// in a single datagram is sent as is, anything larger is cut into fragments
+///
+/// ```text
+/// +-------+----------+-------+-------+---------------------------+
+/// | magic | frame_id | index | count | slice of the message      |
+/// |  1B   |   16B    |  2B   |  2B   | up to FRAGMENT_PAYLOAD_LEN |
+/// +-------+----------+-------+-------+---------------------------+
+/// ```
+///
+/// Returns `None` if the message needs more fragments than `count` can hold.
+pub fn fragment(encoded: &[u8]) -> Option<Vec<Vec<u8>>> {
+    if encoded.len() <= MAX_DATAGRAM_LEN {
+        return Some(vec![encoded.to_vec()]);
+    }
+
+    let chunks: Vec<&[u8]> = encoded.chunks(FRAGMENT_PAYLOAD_LEN).collect();
+    let count = u16::try_from(chunks.len()).ok()?;
+    // every retransmission of the message reuses these fragments, so the
+    // receiver can complete a message from pieces of different attempts
+    let frame_id = Uuid::new_v4();
+
+    let fragments = chunks.iter().enumerate().map(|(index, chunk)| {
+        let mut datagram = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+        datagram.push(FRAGMENT_MAGIC);
+        datagram.extend_from_slice(frame_id.as_bytes());
+        datagram.extend_from_slice(&(index as u16).to_be_bytes());
+        datagram.extend_from_slice(&count.to_be_bytes());
+        datagram.extend_from_slice(chunk);
+        datagram
+    }).collect();
+    Some(fragments)
+}
+
+/// why a datagram could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+    /// the fragment header is truncated or inconsistent
+    Malformed,
+    /// the message would be larger than the receiver accepts
+    TooLarge,
+    /// too many messages are waiting for fragments already, from the sender
+    /// or overall
+    TooManyPartials,
+}
+
+/// a message of which only some fragments arrived so far
+struct Partial {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    first_seen: Instant,
+}
+
+/// collects fragments until their message is complete. Messages missing
+/// fragments for longer than `timeout` are dropped, the sender will
+/// retransmit them as a whole
+///
+/// Fragments are told apart by their source `S`, by default the address they
+/// came from. Datagrams that all come from one address on behalf of several
+/// senders, e.g. through a relay, are better told apart by sender, so that
+/// each gets a limit of its own
+pub struct Reassembler<S = SocketAddr> {
+    partials: HashMap<(S, Uuid), Partial>,
+    timeout: Duration,
+    max_message_size: usize,
+}
+
+impl<S: Copy + Eq + Hash> Reassembler<S> {
+    /// `timeout`: how long to wait for the missing fragments of a message
+    /// `max_message_size`: largest encoded message accepted, in bytes
+    pub fn new(timeout: Duration, max_message_size: usize) -> Reassembler<S> {
+        Reassembler { partials: HashMap::new(), timeout, max_message_size }
+    }
+
+    /// takes a received datagram. Returns the encoded message once it is
+    /// complete, which is straight away for datagrams that aren't fragments
+    ///
+    /// `source`: who the datagram came from
+    /// `datagram`: the received bytes
+    /// `now`: the time of reception
+    pub fn push(&mut self, source: S, datagram: &[u8],
+                now: Instant) -> Result<Option<Vec<u8>>, FragmentError> {
+        self.expire(now);
+
+        if datagram.first() != Some(&FRAGMENT_MAGIC) {
+            if datagram.len() > self.max_message_size {
+                return Err(FragmentError::TooLarge);
+            }
+            return Ok(Some(datagram.to_vec()));
+        }
+
+        if datagram.len() < FRAGMENT_HEADER_LEN {
+            return Err(FragmentError::Malformed);
+        }
+        let frame_id = Uuid::from_bytes(datagram[1..17].try_into().unwrap());
+        let index = u16::from_be_bytes([datagram[17], datagram[18]]) as usize;
+        let count = u16::from_be_bytes([datagram[19], datagram[20]]) as usize;
+        let payload = &datagram[FRAGMENT_HEADER_LEN..];
+
+        if index >= count || payload.is_empty() {
+            return Err(FragmentError::Malformed);
+        }
+        // checked before anything is buffered, so a bogus count can't make
+        // us hold on to more than `max_message_size`
+        if count.saturating_mul(FRAGMENT_PAYLOAD_LEN)
+            > self.max_message_size + FRAGMENT_PAYLOAD_LEN {
+            return Err(FragmentError::TooLarge);
+        }
+
+        // a new message can't go past the limits, the sender will retransmit
+        // it once some of the others are complete or expired
+        if !self.partials.contains_key(&(source, frame_id)) {
+            let from_src = self.partials.keys()
+                .filter(|(other, _)| *other == source)
+                .count();
+            if from_src >= MAX_PARTIALS_PER_SOURCE 
+                || self.partials.len() >= MAX_PARTIALS {
+                return Err(FragmentError::TooManyPartials);
+            }
+        }
+
+        let partial = self.partials.entry((source, frame_id))
+            .or_insert_with(|| Partial {
+                fragments: vec![None; count],
+                received: 0,
+                first_seen: now,
+            });
+        if partial.fragments.len() != count {
+            return Err(FragmentError::Malformed);
+        }
+        // retransmitted fragments are already known
+        if partial.fragments[index].is_none() {
+            partial.fragments[index] = Some(payload.to_vec());
+            partial.received += 1;
+        }
+        if partial.received < count {
+            return Ok(None);
+        }
+
+        let partial = self.partials.remove(&(source, frame_id)).unwrap();
+        let message: Vec<u8> = partial.fragments.into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        if message.len() > self.max_message_size {
+            return Err(FragmentError::TooLarge);
+        }
+        Ok(Some(message))
+    }
+
+    /// drops messages that have been missing fragments for too long
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        self.partials.retain(|_, partial|
+                             now.duration_since(partial.first_seen) < timeout);
+    }
+
+    /// number of messages currently waiting for fragments
+    pub fn pending(&self) -> usize {
+        self.partials.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn src_addr() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 50_001))
+    }
+
+    fn encoded_message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn small_message_is_not_fragmented() {
+        let encoded = encoded_message(MAX_DATAGRAM_LEN);
+        let datagrams = fragment(&encoded).unwrap();
+        assert_eq!(datagrams, vec![encoded.clone()]);
+
+        let mut reassembler = Reassembler::new(TIMEOUT, 1 << 20);
+        let reassembled = reassembler.push(src_addr(), &datagrams[0],
+                                           Instant::now());
+        assert_eq!(reassembled, Ok(Some(encoded)));
+    }
+
+    #[test]
+    fn fragments_reassemble_in_any_order() {
+        let encoded = encoded_message(10 * MAX_DATAGRAM_LEN + 7);
+        let mut datagrams = fragment(&encoded).unwrap();
+        assert!(datagrams.iter().all(|datagram|
+                                     datagram.len() <= MAX_DATAGRAM_LEN));
+        datagrams.reverse();
+
+        let mut reassembler = Reassembler::new(TIMEOUT, 1 << 20);
+        let now = Instant::now();
+        let (last, rest) = datagrams.split_last().unwrap();
+        for datagram in rest {
+            assert_eq!(reassembler.push(src_addr(), datagram, now), Ok(None));
+            // duplicates from retransmissions don't count twice
+            assert_eq!(reassembler.push(src_addr(), datagram, now), Ok(None));
+        }
+        assert_eq!(reassembler.push(src_addr(), last, now), Ok(Some(encoded)));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn incomplete_message_expires() {
+        let datagrams = fragment(&encoded_message(3 * MAX_DATAGRAM_LEN))
+            .unwrap();
+
+        let mut reassembler = Reassembler::new(TIMEOUT, 1 << 20);
+        let now = Instant::now();
+        assert_eq!(reassembler.push(src_addr(), &datagrams[0], now), Ok(None));
+        assert_eq!(reassembler.pending(), 1);
+
+        reassembler.expire(now + TIMEOUT);
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn oversized_message_is_refused() {
+        let datagrams = fragment(&encoded_message(8 * MAX_DATAGRAM_LEN))
+            .unwrap();
+
+        let mut reassembler = Reassembler::new(TIMEOUT, 2 * MAX_DATAGRAM_LEN);
+        assert_eq!(reassembler.push(src_addr(), &datagrams[0], Instant::now()),
+                   Err(FragmentError::TooLarge));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn partials_are_limited_per_source_and_overall() {
+        let mut reassembler = Reassembler::new(TIMEOUT, 1 << 20);
+        let now = Instant::now();
+        let first_fragment = || 
+            fragment(&encoded_message(2 * MAX_DATAGRAM_LEN)).unwrap()
+                .swap_remove(0);
+
+        for _ in 0..MAX_PARTIALS_PER_SOURCE {
+            assert_eq!(reassembler.push(src_addr(), &first_fragment(), now),
+                       Ok(None));
+        }
+        assert_eq!(reassembler.push(src_addr(), &first_fragment(), now),
+                   Err(FragmentError::TooManyPartials));
+
+        // other senders have limits of their own, up to the overall one
+        for port in 0..(MAX_PARTIALS - MAX_PARTIALS_PER_SOURCE) as u16 {
+            let other_addr = SocketAddr::from(([127, 0, 0, 2], port));
+            assert_eq!(reassembler.push(other_addr, &first_fragment(), now),
+                       Ok(None));
+        }
+        let other_addr = SocketAddr::from(([127, 0, 0, 3], 50_001));
+        assert_eq!(reassembler.push(other_addr, &first_fragment(), now),
+                   Err(FragmentError::TooManyPartials));
+        assert_eq!(reassembler.pending(), MAX_PARTIALS);
+
+        // fragments of messages already waiting still get through
+        let datagrams = fragment(&encoded_message(2 * MAX_DATAGRAM_LEN))
+            .unwrap();
+        let mut full = Reassembler::new(TIMEOUT, 1 << 20);
+        assert_eq!(full.push(src_addr(), &datagrams[0], now), Ok(None));
+        for _ in 1..MAX_PARTIALS_PER_SOURCE {
+            full.push(src_addr(), &first_fragment(), now).unwrap();
+        }
+        let (last, rest) = datagrams[1..].split_last().unwrap();
+        for datagram in rest {
+            assert_eq!(full.push(src_addr(), datagram, now), Ok(None));
+        }
+        assert!(full.push(src_addr(), last, now).unwrap().is_some());
+    }
+
+    #[test]
+    fn senders_behind_one_address_get_limits_of_their_own() {
+        // e.g. peers relayed by the server, told apart by their UUID
+        let mut reassembler = Reassembler::new(TIMEOUT, 1 << 20);
+        let now = Instant::now();
+        let first_fragment = || 
+            fragment(&encoded_message(2 * MAX_DATAGRAM_LEN)).unwrap()
+                .swap_remove(0);
+        let (flooder, other) = (Uuid::new_v4(), Uuid::new_v4());
+
+        for _ in 0..MAX_PARTIALS_PER_SOURCE {
+            reassembler.push(flooder, &first_fragment(), now).unwrap();
+        }
+        assert_eq!(reassembler.push(flooder, &first_fragment(), now),
+                   Err(FragmentError::TooManyPartials));
+        assert_eq!(reassembler.push(other, &first_fragment(), now), Ok(None));
+    }
+}
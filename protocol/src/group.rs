@@ -0,0 +1,125 @@
+/*
+ * File: group.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: named groups of peers, addressed with a group id the same way
+ * a single peer is addressed with its UUID
+ */
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// most members a group may have. Every message to the group carries the
+/// member list, so it has to stay small next to the message itself
+pub const MAX_GROUP_MEMBERS: usize = 64;
+
+/// a named set of peers. A message sent to the group is sent to each member
+/// on its own, tagged with the group so that it is displayed in context
+///
+/// Members learn about changes from the tags of the messages sent after them,
+/// so every change bumps the `version`, and an older tag doesn't undo it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Group {
+    pub id: Uuid,
+    pub name: String,
+    pub creator: Uuid,
+    pub version: u64,       // bumped by every change of the members
+    pub members: Vec<Uuid>,
+}
+
+impl Group {
+    /// creates a group with a fresh id. Duplicate members are dropped
+    ///
+    /// `name`: displayed along with the group's messages
+    /// `creator`: the peer creating the group, its first member
+    /// `members`: uuids of the other peers in the group
+    pub fn new(name: &str, creator: Uuid, members: &[Uuid]) -> Group {
+        let mut group = Group {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            creator,
+            version: 0,
+            members: vec![creator],
+        };
+        for member in members {
+            group.add_member(*member);
+        }
+        group.version = 0;
+        group
+    }
+
+    /// whether a copy of the group received from `sender` replaces ours.
+    /// The creator's copy always does. Anybody else's only if it is newer,
+    /// and leaves the name alone
+    ///
+    /// `local`: our copy of the group
+    /// `sender`: the peer whose message carried this copy
+    pub fn supersedes(&self, local: &Group, sender: &Uuid) -> bool {
+        if self.id != local.id || self.creator != local.creator {
+            return false;
+        }
+        *sender == self.creator 
+            || (self.version > local.version && self.name == local.name)
+    }
+
+    pub fn is_member(&self, uuid: &Uuid) -> bool {
+        self.members.contains(uuid)
+    }
+
+    /// adds a member, returns false if it already was one or the group is
+    /// full
+    pub fn add_member(&mut self, uuid: Uuid) -> bool {
+        if self.is_member(&uuid) || self.members.len() >= MAX_GROUP_MEMBERS {
+            return false;
+        }
+        self.members.push(uuid);
+        self.version += 1;
+        true
+    }
+
+    /// removes a member, returns false if it wasn't one
+    pub fn remove_member(&mut self, uuid: &Uuid) -> bool {
+        let len = self.members.len();
+        self.members.retain(|member| member != uuid);
+        if self.members.len() == len {
+            return false;
+        }
+        self.version += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn older_copies_dont_undo_changes() {
+        let (creator, member, other) = 
+            (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let sent = Group::new("team", creator, &[member]);
+        let mut local = sent.clone();
+        local.add_member(other);
+
+        // a message sent before the change still carries the old copy
+        assert!(!sent.supersedes(&local, &member));
+        assert!(local.supersedes(&sent, &member));
+        // the creator's copy is taken as is
+        assert!(sent.supersedes(&local, &creator));
+    }
+
+    #[test]
+    fn members_cant_rename_or_take_over_the_group() {
+        let (creator, member) = (Uuid::new_v4(), Uuid::new_v4());
+        let local = Group::new("team", creator, &[member]);
+
+        let mut renamed = local.clone();
+        renamed.name = "hijacked".to_string();
+        renamed.version += 1;
+        assert!(!renamed.supersedes(&local, &member));
+
+        let mut taken_over = local.clone();
+        taken_over.creator = member;
+        assert!(!taken_over.supersedes(&local, &member));
+    }
+}
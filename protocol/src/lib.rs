@@ -0,0 +1,52 @@
+/*
+ * File: lib.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: wire protocol spoken between clients and the central index
+ * server, and between peers. Shared by both binaries so that they agree on
+ * every field.
+ */
+pub mod codec;
+pub mod crypto;
+pub mod fragment;
+pub mod group;
+pub mod message;
+pub mod net;
+pub mod punch;
+pub mod request;
+pub mod response;
+pub mod settings;
+pub mod transfer;
+
+pub use codec::Codec;
+pub use request::{
+    AckMail, Deposit, Deregistration, FetchMail, Heartbeat, LookupByName, 
+    Punch, Query, Registration, Relay, Request, RequestBody, Watch,
+};
+pub use response::{ErrorCode, MailItem, PeerStatus, Response, ResponseBody};
+
+/// version of the protocol implemented by this crate. Sent with every request
+/// and response, peers speaking another version are refused
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// port the central index server listens on
+pub const DEFAULT_SERVER_PORT: u16 = 50_000;
+
+/// lease asked for by requests that don't name a ttl
+pub const DEFAULT_LEASE_TTL_SECS: u64 = 60;
+
+/// how long a client keeps using a looked up address before looking it up
+/// again, and how long the server tells it when that address changes
+pub const PEER_CACHE_TTL_SECS: u64 = 300;
+
+/// parses a json message and checks that it speaks `PROTOCOL_VERSION`
+fn check_version(bytes: &[u8]) -> Result<serde_json::Value, ErrorCode> {
+    let value: serde_json::Value = serde_json::from_slice(bytes)
+        .map_err(|_| ErrorCode::MalformedRequest)?;
+    match value["version"].as_u64() {
+        Some(version) if version == PROTOCOL_VERSION as u64 => Ok(value),
+        Some(_) => Err(ErrorCode::UnsupportedVersion),
+        None => Err(ErrorCode::MalformedRequest),
+    }
+}
@@ -0,0 +1,684 @@
+/*
+ * File: message.rs
+ * Author: Ethan Graham
+ * Date: 07 Feb. 2024
+ *
+ * Description: contains Message struct and implementations
+ */
+use chrono::{DateTime, Local};
+use uuid::Uuid;
+use json::JsonValue;
+use x25519_dalek::PublicKey;
+use crate::codec::{Codec, BINARY_MAGIC};
+use crate::crypto::{self, KeyPair};
+use crate::group::{Group, MAX_GROUP_MEMBERS};
+
+// prevent typos
+static DST_UUID_FIELD: &str = "dst_uuid";
+static SRC_UUID_FIELD: &str = "src_uuid";
+static CREATION_TIME_FIELD: &str = "creation_time";
+static DATA_FIELD: &str = "data";
+static MSG_ID_FIELD: &str = "msg_id";
+static MSG_TYPE_FIELD: &str = "msg_type";
+static SRC_PUBLIC_KEY_FIELD: &str = "src_public_key";
+static NONCE_FIELD: &str = "nonce";
+
+/// size of a binary frame without the encrypted data
+static BINARY_HEADER_LEN: usize = 1 + 1 + 16 + 16 + 16 + 32 + 8 + 12 + 4;
+
+/// the kind of a message. Every message other than an `Ack` is answered with
+/// an `Ack` carrying the same `msg_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Data = 0,
+    Ack = 1,
+    /// the `File*` messages carry a json payload from `transfer`, and their
+    /// acks carry the recipient's `FileReply`
+    FileOffer = 2,
+    FileChunk = 3,
+    FileEnd = 4,
+}
+
+impl MessageType {
+    /// the value used for this type in the `msg_type` field
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageType::Data => "data",
+            MessageType::Ack => "ack",
+            MessageType::FileOffer => "file_offer",
+            MessageType::FileChunk => "file_chunk",
+            MessageType::FileEnd => "file_end",
+        }
+    }
+
+    /// parses the value of a `msg_type` field
+    pub fn parse(msg_type: &str) -> Option<MessageType> {
+        match msg_type {
+            "data" => Some(MessageType::Data),
+            "ack" => Some(MessageType::Ack),
+            "file_offer" => Some(MessageType::FileOffer),
+            "file_chunk" => Some(MessageType::FileChunk),
+            "file_end" => Some(MessageType::FileEnd),
+            _ => None,
+        }
+    }
+
+    /// parses the `msg_type` byte of a binary frame
+    pub fn from_u8(msg_type: u8) -> Option<MessageType> {
+        match msg_type {
+            0 => Some(MessageType::Data),
+            1 => Some(MessageType::Ack),
+            2 => Some(MessageType::FileOffer),
+            3 => Some(MessageType::FileChunk),
+            4 => Some(MessageType::FileEnd),
+            _ => None,
+        }
+    }
+}
+
+/// represents a message created by a peer
+pub struct Message {
+    pub msg_id: Uuid,
+    pub msg_type: MessageType,
+    pub dst_uuid: Uuid,
+    pub src_uuid: Uuid,
+    pub creation_time: DateTime<Local>,
+    pub data: String,
+    /// the sender's public key. Only set for messages received from a peer
+    pub src_public_key: Option<PublicKey>,
+    /// the group the message was sent to, if it wasn't sent to `dst_uuid`
+    /// alone. Encrypted along with `data`
+    pub group: Option<Group>,
+}
+
+impl Message {
+    /// creates a new message. Returns an `Err` if `dst_uuid` and `src_uuid`
+    /// are the same
+    pub fn new(dst_uuid: Uuid, src_uuid: Uuid, data: &str) 
+        -> Result<Message, MessageError>{
+        Message::new_with_timestamp(dst_uuid, src_uuid, data, Local::now())
+    }
+
+    pub fn new_with_timestamp(dst_uuid: Uuid, src_uuid: Uuid, data: &str, 
+               creation_time: DateTime<Local>) -> Result<Message, MessageError>{
+        
+        /*
+        if dst_uuid == src_uuid {
+            let err_msg = "dst_uuid and src_uuid should be different!"
+                .to_string();
+            return Err(MessageError::MessageCreationError(err_msg));
+        }
+        */
+
+        let gen_msg = Message {
+            msg_id: Uuid::new_v4(),
+            msg_type: MessageType::Data,
+            dst_uuid,
+            src_uuid,
+            data: data.to_string(),
+            creation_time,
+            src_public_key: None,
+            group: None,
+        };
+
+        Ok(gen_msg)
+    }
+
+    /// creates the acknowledgement for a received message. The ack travels in
+    /// the opposite direction and reuses the `msg_id` of the original
+    pub fn new_ack(&self) -> Message {
+        self.new_reply("")
+    }
+
+    /// creates an acknowledgement that also answers the received message,
+    /// with `data` as the answer
+    pub fn new_reply(&self, data: &str) -> Message {
+        Message {
+            msg_id: self.msg_id,
+            msg_type: MessageType::Ack,
+            dst_uuid: self.src_uuid,
+            src_uuid: self.dst_uuid,
+            creation_time: Local::now(),
+            data: data.to_string(),
+            src_public_key: None,
+            group: None,
+        }
+    }
+
+    /// decodes a received datagram, whichever codec it was encoded with.
+    /// Called for incoming messages.
+    ///
+    /// Returns the message along with the codec it arrived in, so that the
+    /// reply can be sent in the same one.
+    pub fn decode(bytes: &[u8], keys: &KeyPair) 
+        -> Result<(Message, Codec), MessageError> {
+        match Codec::detect(bytes) {
+            Some(Codec::Json) => {
+                let json_data = std::str::from_utf8(bytes).ok()
+                    .and_then(|json_str| json::parse(json_str).ok());
+                match json_data {
+                    Some(json_data) => Ok((Message::from_json(json_data, keys)?,
+                                           Codec::Json)),
+                    None => Err(MessageError::JsonParseError(
+                            "Invalid JSON".to_string())),
+                }
+            },
+            Some(Codec::Binary) => 
+                Ok((Message::from_binary(bytes, keys)?, Codec::Binary)),
+            None => Err(MessageError::DecodeError(
+                    "Unknown message encoding".to_string())),
+        }
+    }
+
+    /// encodes a `Message` with `codec` for sending. `data` is encrypted with
+    /// `keys` for the recipient holding `dst_public_key`
+    pub fn encode(&self, codec: Codec, keys: &KeyPair, 
+                  dst_public_key: &PublicKey) -> Vec<u8> {
+        match codec {
+            Codec::Json => 
+                self.to_json(keys, dst_public_key).dump().into_bytes(),
+            Codec::Binary => self.to_binary(keys, dst_public_key),
+        }
+    }
+
+    /// creates a message from Json data.
+    ///
+    /// `data` is decrypted with `keys` and the sender's public key. Returns a
+    /// `MessageAuthenticationError` if the payload does not authenticate, i.e.
+    /// it was tampered with or was not encrypted for `keys`.
+    ///
+    /// Assumes the following format:
+    /// ```text
+    /// {
+    ///     "msg_id"        : "<128-bit message id>",
+    ///     "msg_type"      : "data" | "ack" | "file_offer" | "file_chunk"
+    ///                       | "file_end",     (see MessageType::parse())
+    ///     "dst_uuid"      : "<some 128-bit value>",
+    ///     "src_uuid"      : "<different 128-bit value>",
+    ///     "src_public_key": "<sender's x25519 public key, hex>",
+    ///     "nonce"         : "<96-bit AEAD nonce, hex>",
+    ///     "data"          : "<encrypted group and data, hex>",
+    ///     "creation_time" : "<RFC 3339 timestamp>"
+    /// }
+    /// ```
+    pub fn from_json(json_data: JsonValue, keys: &KeyPair) 
+        -> Result<Message, MessageError> {
+        let dst_uuid        = &json_data[DST_UUID_FIELD];
+        let src_uuid        = &json_data[SRC_UUID_FIELD];
+        let data            = &json_data[DATA_FIELD];
+        let creation_time   = &json_data[CREATION_TIME_FIELD];
+        let msg_id          = &json_data[MSG_ID_FIELD];
+        let msg_type        = &json_data[MSG_TYPE_FIELD];
+        let src_public_key  = &json_data[SRC_PUBLIC_KEY_FIELD];
+        let nonce           = &json_data[NONCE_FIELD];
+
+        let msg_id = match msg_id.to_string().parse::<Uuid>() {
+            Ok(valid_uuid) => valid_uuid,
+            Err(err) => {
+                let err_msg = format!("Error parsing msg_id: {}", err);
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        let msg_type = match MessageType::parse(&msg_type.to_string()) {
+            Some(valid_type) => valid_type,
+            None => {
+                let err_msg = format!("Unknown msg_type: {}", msg_type);
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        // parse dst_uuid into a Uuid object
+        let dst_uuid = match dst_uuid.to_string().parse::<Uuid>() {
+            Ok(valid_uuid) => valid_uuid,
+            Err(err) => {
+                let err_msg = format!("Error parsing dst_uuid: {}", err);
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        // parse dst_uuid into a Uuid object
+        let src_uuid = match src_uuid.to_string().parse::<Uuid>() {
+            Ok(valid_uuid) => valid_uuid,
+            Err(err) => {
+                let err_msg = format!("Error parsing src_uuid: {}", err);
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        let src_public_key = match crypto::parse_public_key(
+            &src_public_key.to_string()) {
+            Some(valid_key) => valid_key,
+            None => {
+                let err_msg = "Error parsing src_public_key".to_string();
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        let (nonce, ciphertext) = match (hex::decode(nonce.to_string()),
+                                         hex::decode(data.to_string())) {
+            (Ok(nonce), Ok(ciphertext)) => (nonce, ciphertext),
+            _ => {
+                let err_msg = "Error decoding nonce or data".to_string();
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        // parse creation_time into a valid DateTime<Local>
+        let creation_time = match DateTime::parse_from_rfc3339(
+            &creation_time.to_string()) {
+            Ok(valid_datetime) => valid_datetime.with_timezone(&Local),
+            Err(err) => {
+                let err_msg = format!("Error parsing creation_time: {}", err);
+                return Err(MessageError::JsonParseError(err_msg));
+            }
+        };
+
+        let header = Header { 
+            msg_id, msg_type, dst_uuid, src_uuid, src_public_key, creation_time
+        };
+        header.open(keys, &nonce, &ciphertext)
+    }
+
+    /// parses a `Message` to json and returns this value. `data` is encrypted
+    /// with `keys` for the recipient holding `dst_public_key`
+    pub fn to_json(&self, keys: &KeyPair, dst_public_key: &PublicKey) 
+        -> JsonValue {
+        let aad = associated_data(&self.msg_id, self.msg_type, &self.src_uuid,
+                                  &self.dst_uuid, &self.creation_time);
+        let (nonce, ciphertext) = keys.seal(dst_public_key, &aad, 
+                                            &self.plaintext());
+
+        let mut json_val = JsonValue::new_object();
+        json_val[MSG_ID_FIELD] = JsonValue::from(self.msg_id.to_string());
+        json_val[MSG_TYPE_FIELD] = JsonValue::from(self.msg_type.as_str());
+        json_val[DST_UUID_FIELD] = JsonValue::from(self.dst_uuid.to_string());
+        json_val[SRC_UUID_FIELD] = JsonValue::from(self.src_uuid.to_string());
+        json_val[SRC_PUBLIC_KEY_FIELD] = JsonValue::from(keys.public_key_hex());
+        json_val[NONCE_FIELD] = JsonValue::from(hex::encode(nonce));
+        json_val[DATA_FIELD] = JsonValue::from(hex::encode(ciphertext));
+        json_val[CREATION_TIME_FIELD] = JsonValue::from(self.creation_time
+                                                    .to_rfc3339());
+        json_val
+    }
+
+    /// creates a message from a binary frame, as written by `to_binary()`.
+    /// `data` is decrypted and authenticated like in `from_json()`
+    pub fn from_binary(bytes: &[u8], keys: &KeyPair) 
+        -> Result<Message, MessageError> {
+        let mut reader = FrameReader { bytes };
+
+        if reader.take(1)? != [BINARY_MAGIC] {
+            return Err(MessageError::DecodeError(
+                    "Not a binary frame".to_string()));
+        }
+
+        let msg_type = match MessageType::from_u8(reader.take(1)?[0]) {
+            Some(valid_type) => valid_type,
+            None => return Err(MessageError::DecodeError(
+                    "Unknown msg_type".to_string())),
+        };
+        let msg_id = reader.take_uuid()?;
+        let dst_uuid = reader.take_uuid()?;
+        let src_uuid = reader.take_uuid()?;
+        let src_public_key: [u8; 32] = reader.take(32)?.try_into().unwrap();
+        let creation_micros = 
+            i64::from_be_bytes(reader.take(8)?.try_into().unwrap());
+        let nonce = reader.take(crypto::NONCE_LEN)?;
+        let data_len = 
+            u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+        let ciphertext = reader.take(data_len)?;
+
+        if !reader.bytes.is_empty() {
+            return Err(MessageError::DecodeError(
+                    "Trailing bytes after frame".to_string()));
+        }
+
+        let creation_time = match DateTime::from_timestamp_micros(
+            creation_micros) {
+            Some(valid_datetime) => valid_datetime.with_timezone(&Local),
+            None => return Err(MessageError::DecodeError(
+                    "Invalid creation_time".to_string())),
+        };
+
+        let header = Header {
+            msg_id,
+            msg_type,
+            dst_uuid,
+            src_uuid,
+            src_public_key: PublicKey::from(src_public_key),
+            creation_time,
+        };
+        header.open(keys, nonce, ciphertext)
+    }
+
+    /// encodes a `Message` as a binary frame. `data` is encrypted with `keys`
+    /// for the recipient holding `dst_public_key`. Integers are big endian
+    ///
+    /// ```text
+    /// +-------+----------+--------+----------+----------+----------------+
+    /// | magic | msg_type | msg_id | dst_uuid | src_uuid | src_public_key |
+    /// |  1B   |    1B    |  16B   |   16B    |   16B    |      32B       |
+    /// +-------+----------+--------+----------+----------+----------------+
+    /// | creation_time (unix micros) | nonce | data length | data (encrypted) |
+    /// |             8B              |  12B  |     4B      |   data length    |
+    /// +-----------------------------+-------+-------------+------------------+
+    /// ```
+    pub fn to_binary(&self, keys: &KeyPair, dst_public_key: &PublicKey) 
+        -> Vec<u8> {
+        let aad = associated_data(&self.msg_id, self.msg_type, &self.src_uuid,
+                                  &self.dst_uuid, &self.creation_time);
+        let (nonce, ciphertext) = keys.seal(dst_public_key, &aad, 
+                                            &self.plaintext());
+
+        let mut frame = Vec::with_capacity(BINARY_HEADER_LEN + ciphertext.len());
+        frame.push(BINARY_MAGIC);
+        frame.push(self.msg_type as u8);
+        frame.extend_from_slice(self.msg_id.as_bytes());
+        frame.extend_from_slice(self.dst_uuid.as_bytes());
+        frame.extend_from_slice(self.src_uuid.as_bytes());
+        frame.extend_from_slice(keys.public.as_bytes());
+        frame.extend_from_slice(
+            &self.creation_time.timestamp_micros().to_be_bytes());
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&ciphertext);
+        frame
+    }
+
+    /// the part of the message that gets encrypted, the same in every codec.
+    /// The group is json encoded, and left out for messages to a single peer
+    ///
+    /// ```text
+    /// +--------------+--------------+------+
+    /// | group length | group        | data |
+    /// |      4B      | group length |      |
+    /// +--------------+--------------+------+
+    /// ```
+    fn plaintext(&self) -> Vec<u8> {
+        let group = match &self.group {
+            // can't fail, a group always serializes to json
+            Some(group) => serde_json::to_vec(group).unwrap(),
+            None => Vec::new(),
+        };
+        let mut plaintext = Vec::with_capacity(4 + group.len() 
+                                               + self.data.len());
+        plaintext.extend_from_slice(&(group.len() as u32).to_be_bytes());
+        plaintext.extend_from_slice(&group);
+        plaintext.extend_from_slice(self.data.as_bytes());
+        plaintext
+    }
+}
+
+/// the unencrypted fields of a received message, whichever codec it came in
+struct Header {
+    msg_id: Uuid,
+    msg_type: MessageType,
+    dst_uuid: Uuid,
+    src_uuid: Uuid,
+    src_public_key: PublicKey,
+    creation_time: DateTime<Local>,
+}
+
+impl Header {
+    /// decrypts and authenticates `ciphertext` against the header, yielding
+    /// the received message
+    fn open(self, keys: &KeyPair, nonce: &[u8], ciphertext: &[u8]) 
+        -> Result<Message, MessageError> {
+        let aad = associated_data(&self.msg_id, self.msg_type, &self.src_uuid,
+                                  &self.dst_uuid, &self.creation_time);
+        let plaintext = match keys.open(&self.src_public_key, nonce, &aad, 
+                                        ciphertext) {
+            Some(plaintext) => plaintext,
+            None => {
+                let err_msg = format!("Message {} failed authentication", 
+                                      self.msg_id);
+                return Err(MessageError::MessageAuthenticationError(err_msg));
+            }
+        };
+        let (group, data) = parse_plaintext(&plaintext)?;
+
+        Ok(Message {
+            msg_id: self.msg_id,
+            msg_type: self.msg_type,
+            dst_uuid: self.dst_uuid,
+            src_uuid: self.src_uuid,
+            creation_time: self.creation_time,
+            data,
+            src_public_key: Some(self.src_public_key),
+            group,
+        })
+    }
+}
+
+/// splits a decrypted plaintext, as written by `Message::plaintext()`, into
+/// the group and the data
+fn parse_plaintext(plaintext: &[u8]) 
+    -> Result<(Option<Group>, String), MessageError> {
+    let mut reader = FrameReader { bytes: plaintext };
+    let group_len = 
+        u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+    let group = match group_len {
+        0 => None,
+        _ => match serde_json::from_slice::<Group>(reader.take(group_len)?) {
+            Ok(group) if group.members.len() <= MAX_GROUP_MEMBERS => 
+                Some(group),
+            _ => return Err(MessageError::DecodeError(
+                    "Invalid group".to_string())),
+        },
+    };
+
+    match String::from_utf8(reader.bytes.to_vec()) {
+        Ok(data) => Ok((group, data)),
+        Err(_) => Err(MessageError::DecodeError(
+                "Data is not valid UTF-8".to_string())),
+    }
+}
+
+/// reads the fields of a binary frame front to back
+struct FrameReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> FrameReader<'a> {
+    /// the next `len` bytes, or an error if the frame is too short
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MessageError> {
+        if self.bytes.len() < len {
+            return Err(MessageError::DecodeError(
+                    "Truncated binary frame".to_string()));
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    fn take_uuid(&mut self) -> Result<Uuid, MessageError> {
+        Ok(Uuid::from_bytes(self.take(16)?.try_into().unwrap()))
+    }
+}
+
+/// the header fields bound to the ciphertext, so that a payload cannot be
+/// replayed under a different id, type, sender, recipient or creation time.
+/// The creation time is bound in microseconds, the precision of the binary
+/// codec, so that both codecs authenticate the same bytes
+fn associated_data(msg_id: &Uuid, msg_type: MessageType, src_uuid: &Uuid,
+                   dst_uuid: &Uuid, creation_time: &DateTime<Local>) 
+    -> Vec<u8> {
+    let mut aad = Vec::with_capacity(57);
+    aad.extend_from_slice(msg_id.as_bytes());
+    aad.push(msg_type as u8);
+    aad.extend_from_slice(src_uuid.as_bytes());
+    aad.extend_from_slice(dst_uuid.as_bytes());
+    aad.extend_from_slice(&creation_time.timestamp_micros().to_be_bytes());
+    aad
+}
+
+/* error handling */
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum MessageError {
+    MessageCreationError(String),
+    JsonParseError(String),
+    DecodeError(String),
+    MessageAuthenticationError(String),
+}
+
+impl error::Error for MessageError {}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::MessageCreationError(msg) =>
+                write!(f, "MessageError: {}", msg),
+            MessageError::JsonParseError(msg) =>
+                write!(f, "JsonParseError: {}", msg),
+            MessageError::DecodeError(msg) =>
+                write!(f, "DecodeError: {}", msg),
+            MessageError::MessageAuthenticationError(msg) =>
+                write!(f, "MessageAuthenticationError: {}", msg),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::SUPPORTED_CODECS;
+
+    /// a message between two fresh random uuids
+    fn sample_message(data: &str) -> Message {
+        Message::new(Uuid::new_v4(), Uuid::new_v4(), data).unwrap()
+    }
+
+    fn assert_same_message(received: &Message, sent: &Message, 
+                           sender: &KeyPair) {
+        assert_eq!(received.msg_id, sent.msg_id);
+        assert_eq!(received.msg_type, sent.msg_type);
+        assert_eq!(received.dst_uuid, sent.dst_uuid);
+        assert_eq!(received.src_uuid, sent.src_uuid);
+        assert_eq!(received.data, sent.data);
+        assert_eq!(received.group, sent.group);
+        assert_eq!(received.creation_time.timestamp_micros(),
+                   sent.creation_time.timestamp_micros());
+        assert_eq!(received.src_public_key, Some(sender.public));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let msg = sample_message("hello there");
+
+        let json_data = msg.to_json(&sender, &recipient.public);
+        let received = Message::from_json(json_data, &recipient).unwrap();
+        assert_same_message(&received, &msg, &sender);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let msg = sample_message("hello there");
+
+        let frame = msg.to_binary(&sender, &recipient.public);
+        let received = Message::from_binary(&frame, &recipient).unwrap();
+        assert_same_message(&received, &msg, &sender);
+    }
+
+    #[test]
+    fn ack_round_trip() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let ack = sample_message("").new_ack();
+
+        for codec in SUPPORTED_CODECS {
+            let bytes = ack.encode(codec, &sender, &recipient.public);
+            let (received, _) = Message::decode(&bytes, &recipient).unwrap();
+            assert_same_message(&received, &ack, &sender);
+        }
+    }
+
+    #[test]
+    fn group_round_trip() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let mut msg = sample_message("hello team");
+        msg.group = Some(Group::new("team", msg.src_uuid, &[msg.dst_uuid]));
+
+        for codec in SUPPORTED_CODECS {
+            let bytes = msg.encode(codec, &sender, &recipient.public);
+            let (received, _) = Message::decode(&bytes, &recipient).unwrap();
+            assert_same_message(&received, &msg, &sender);
+        }
+    }
+
+    #[test]
+    fn decode_detects_codec() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let msg = sample_message("which one am I?");
+
+        for codec in SUPPORTED_CODECS {
+            let bytes = msg.encode(codec, &sender, &recipient.public);
+            let (received, detected) = 
+                Message::decode(&bytes, &recipient).unwrap();
+            assert_eq!(detected, codec);
+            assert_same_message(&received, &msg, &sender);
+        }
+    }
+
+    #[test]
+    fn binary_is_smaller_than_json() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let msg = sample_message("hello there");
+
+        let json_len = msg.encode(Codec::Json, &sender, &recipient.public).len();
+        let binary_len = 
+            msg.encode(Codec::Binary, &sender, &recipient.public).len();
+        assert!(binary_len < json_len);
+        // a message without a group only pays for the group length
+        assert_eq!(binary_len, BINARY_HEADER_LEN + 4 + msg.data.len() + 16);
+    }
+
+    #[test]
+    fn truncated_binary_frame_is_rejected() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let frame = sample_message("hello there")
+            .to_binary(&sender, &recipient.public);
+
+        for len in [0, 1, BINARY_HEADER_LEN, frame.len() - 1] {
+            assert!(matches!(Message::from_binary(&frame[..len], &recipient),
+                             Err(MessageError::DecodeError(_))));
+        }
+    }
+
+    #[test]
+    fn tampered_binary_frame_is_rejected() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let mut frame = sample_message("hello there")
+            .to_binary(&sender, &recipient.public);
+
+        // flip a bit of the dst_uuid, which is bound to the ciphertext
+        let mut tampered = frame.clone();
+        tampered[2 + 16] ^= 1;
+        assert!(matches!(Message::from_binary(&tampered, &recipient),
+                         Err(MessageError::MessageAuthenticationError(_))));
+
+        // and of the creation time, which is too
+        let creation_time_offset = 2 + 3 * 16 + 32;
+        frame[creation_time_offset + 7] ^= 1;
+        assert!(matches!(Message::from_binary(&frame, &recipient),
+                         Err(MessageError::MessageAuthenticationError(_))));
+    }
+
+    #[test]
+    fn tampered_json_creation_time_is_rejected() {
+        let (sender, recipient) = (KeyPair::generate(), KeyPair::generate());
+        let msg = sample_message("hello there");
+        let mut json_data = msg.to_json(&sender, &recipient.public);
+
+        let backdated = msg.creation_time - chrono::Duration::hours(1);
+        json_data[CREATION_TIME_FIELD] = 
+            JsonValue::from(backdated.to_rfc3339());
+        assert!(matches!(Message::from_json(json_data, &recipient),
+                         Err(MessageError::MessageAuthenticationError(_))));
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * File: net.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: UDP sockets shared by IPv4 and IPv6 peers
+ */
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// binds a non-blocking UDP socket, ready for tokio's `UdpSocket::from_std`.
+/// A socket bound to `::` is dual-stack: it is reached over IPv6 and IPv4
+/// alike, whatever the system's default
+pub fn bind_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM,
+                             Some(Protocol::UDP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// the interface to listen on by default: every one, over IPv6 and IPv4 if
+/// the host has IPv6, else over IPv4 only
+pub fn default_listen_ip() -> IpAddr {
+    match bind_udp(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))) {
+        Ok(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        Err(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    }
+}
+
+/// `addr` with IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), as IPv4 peers
+/// are seen by a dual-stack socket, turned back into IPv4 ones. Addresses
+/// are compared and stored in this form
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+/// `dst` in the form a socket bound to `local` sends to, `None` if it can't
+/// reach it. Dual-stack sockets reach IPv4 addresses through their mapped
+/// IPv6 form, other sockets only reach their own family
+pub fn reachable_from(local: &SocketAddr, dst: SocketAddr) 
+    -> Option<SocketAddr> {
+    let dst = canonical(dst);
+    match (local.ip(), dst.ip()) {
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => 
+            Some(dst),
+        (IpAddr::V6(local_ip), IpAddr::V4(dst_ip)) 
+            if local_ip.is_unspecified() => 
+            Some(SocketAddr::new(IpAddr::V6(dst_ip.to_ipv6_mapped()), 
+                                 dst.port())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_addresses_are_ipv4() {
+        let mapped: SocketAddr = "[::ffff:10.0.0.1]:6001".parse().unwrap();
+        let ipv4: SocketAddr = "10.0.0.1:6001".parse().unwrap();
+        let ipv6: SocketAddr = "[2001:db8::1]:6001".parse().unwrap();
+        assert_eq!(canonical(mapped), ipv4);
+        assert_eq!(canonical(ipv6), ipv6);
+    }
+
+    #[test]
+    fn only_dual_stack_sockets_reach_both_families() {
+        let ipv4: SocketAddr = "10.0.0.1:6001".parse().unwrap();
+        let ipv6: SocketAddr = "[2001:db8::1]:6001".parse().unwrap();
+        let dual_stack: SocketAddr = "[::]:6000".parse().unwrap();
+        let ipv6_only: SocketAddr = "[::1]:6000".parse().unwrap();
+        let ipv4_only: SocketAddr = "0.0.0.0:6000".parse().unwrap();
+
+        assert_eq!(reachable_from(&dual_stack, ipv4), 
+                   Some("[::ffff:10.0.0.1]:6001".parse().unwrap()));
+        assert_eq!(reachable_from(&dual_stack, ipv6), Some(ipv6));
+        assert_eq!(reachable_from(&ipv6_only, ipv4), None);
+        assert_eq!(reachable_from(&ipv4_only, ipv4), Some(ipv4));
+        assert_eq!(reachable_from(&ipv4_only, ipv6), None);
+    }
+}
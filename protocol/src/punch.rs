@@ -0,0 +1,44 @@
+/*
+ * File: punch.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: probes sent by peers to each other while punching a hole
+ * through their NATs
+ */
+use uuid::Uuid;
+
+/// first byte of every probe. Distinct from the first byte of both codecs
+/// and of fragments
+pub const PROBE_MAGIC: u8 = 0xf8;
+
+/// a probe from the peer `src_uuid`. Probes only exist to open the sender's
+/// NAT towards the other peer, so they carry nothing else and are dropped on
+/// arrival
+pub fn probe(src_uuid: &Uuid) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(1 + 16);
+    datagram.push(PROBE_MAGIC);
+    datagram.extend_from_slice(src_uuid.as_bytes());
+    datagram
+}
+
+/// whether a received datagram is a probe
+pub fn is_probe(datagram: &[u8]) -> bool {
+    datagram.first() == Some(&PROBE_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::BINARY_MAGIC;
+    use crate::fragment::FRAGMENT_MAGIC;
+
+    #[test]
+    fn probes_are_told_apart() {
+        assert!(is_probe(&probe(&Uuid::new_v4())));
+        assert!(!is_probe(&[BINARY_MAGIC, 0, 0]));
+        assert!(!is_probe(&[FRAGMENT_MAGIC, 0, 0]));
+        assert!(!is_probe(b"{\"msg_id\": \"\"}"));
+        assert!(!is_probe(&[]));
+    }
+}
@@ -0,0 +1,509 @@
+/*
+ * File: request.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: requests sent by clients to the central index server
+ */
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::codec::{default_codecs, Codec};
+use crate::crypto;
+use crate::{
+    check_version, ErrorCode, DEFAULT_LEASE_TTL_SECS, PROTOCOL_VERSION,
+};
+
+/// longest username a client may claim
+pub const MAX_USERNAME_LEN: usize = 32;
+/// shortest username a client may claim
+pub const MIN_USERNAME_LEN: usize = 3;
+/// largest datagram the server relays. Messages are relayed fragment by
+/// fragment, so no datagram sent by a client is larger
+pub const MAX_RELAYED_LEN: usize = crate::fragment::MAX_DATAGRAM_LEN;
+/// largest encoded message that can be left in a mailbox. Mail is fetched in
+/// batches, so it is kept to the size of a single datagram too
+pub const MAX_DEPOSIT_LEN: usize = crate::fragment::MAX_DATAGRAM_LEN;
+
+/// a request to the central index server
+///
+/// ```text
+/// {
+///     "version"   : 1,
+///     "id"        : "<uuid>",     (optional, echoed in the response)
+///     "req_type"  : "registration" | "query" | "lookup_by_name"
+///                   | "heartbeat" | "punch" | "relay" | "deposit"
+///                   | "fetch_mail" | "ack_mail" | "deregistration",
+///     ...fields of the request type
+/// }
+/// ```
+///
+/// The `timestamp` of signed requests is in milliseconds since the unix
+/// epoch, and must increase from one request of a peer to the next.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    /// chosen by the client to tell the response to this request from late
+    /// responses to earlier ones. A retransmitted request keeps its id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(flatten)]
+    pub body: RequestBody,
+}
+
+/// the different requests understood by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "req_type", rename_all = "snake_case")]
+pub enum RequestBody {
+    Registration(Registration),
+    Query(Query),
+    LookupByName(LookupByName),
+    Heartbeat(Heartbeat),
+    Punch(Punch),
+    Relay(Relay),
+    Deposit(Deposit),
+    FetchMail(FetchMail),
+    AckMail(AckMail),
+    Deregistration(Deregistration),
+}
+
+/// registers the sender under the UUID derived from `verifying_key`. Signed
+/// with the matching identity key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registration {
+    pub addr: String,
+    pub public_key: String,
+    pub verifying_key: String,
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    pub timestamp: u64,
+    pub signature: String,
+    /// set by a returning client to the UUID it expects to get back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    /// message codecs the client understands, in order of preference. Not
+    /// signed, both codecs carry the same authenticated ciphertext
+    #[serde(default = "default_codecs")]
+    pub codecs: Vec<Codec>,
+    /// a unique handle others can look the client up by. Registering
+    /// without one gives up the one held so far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+/// looks up a peer's address and public key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Query {
+    pub queried_uuid: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watch: Option<Watch>,
+}
+
+/// looks up a peer by the username it registered with. Answered like a
+/// `Query` for the peer's UUID
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LookupByName {
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watch: Option<Watch>,
+}
+
+/// set on a lookup by a registered peer that wants to be told, for the next
+/// `PEER_CACHE_TTL_SECS`, when the looked up peer's address changes. Signed
+/// by the watching peer, so nobody can have others flooded with updates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watch {
+    pub uuid: Uuid,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// renews the lease of a registered peer. Signed by the peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub uuid: Uuid,
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// sent from the listening socket of a registered peer, so that the server
+/// sees the address the peer's NAT maps that socket to, and the mapping stays
+/// open. With a `target`, also asks the server to have both peers punch a
+/// hole towards each other. Signed by the peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Punch {
+    pub uuid: Uuid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Uuid>,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// asks the server to forward a datagram to a peer that can't be reached
+/// directly. The datagram is an encoded (and encrypted) message or fragment,
+/// which the server can't read. Signed by the sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relay {
+    pub uuid: Uuid,
+    pub target: Uuid,
+    pub datagram: String,       // base64 encoded
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// leaves a message in the mailbox of a registered peer that is offline, for
+/// it to fetch once it registers again. The message is encoded and encrypted
+/// for the recipient, so the server can't read it. Signed by the sender
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub uuid: Uuid,
+    pub target: Uuid,
+    pub message: String,        // base64 encoded
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// fetches the oldest messages in the sender's mailbox. Signed by the peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchMail {
+    pub uuid: Uuid,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// removes fetched messages from the sender's mailbox. Signed by the peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AckMail {
+    pub uuid: Uuid,
+    pub ids: Vec<Uuid>,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+/// removes a peer that is leaving the network. Signed by the peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deregistration {
+    pub uuid: Uuid,
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+impl Request {
+    /// wraps a request body with the current protocol version
+    pub fn new(body: RequestBody) -> Request {
+        Request { version: PROTOCOL_VERSION, id: None, body }
+    }
+
+    /// serializes the request for sending
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // can't fail, every field serializes to json
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// parses a received request. Requests of another protocol version are
+    /// refused before their body is looked at, since it may not parse
+    pub fn from_bytes(bytes: &[u8]) -> Result<Request, ErrorCode> {
+        let value = check_version(bytes)?;
+        serde_json::from_value(value).map_err(|_| ErrorCode::MalformedRequest)
+    }
+}
+
+impl RequestBody {
+    /// name of the request type, for logging
+    pub fn name(&self) -> &'static str {
+        match self {
+            RequestBody::Registration(_) => "registration",
+            RequestBody::Query(_) => "query",
+            RequestBody::LookupByName(_) => "lookup_by_name",
+            RequestBody::Heartbeat(_) => "heartbeat",
+            RequestBody::Punch(_) => "punch",
+            RequestBody::Relay(_) => "relay",
+            RequestBody::Deposit(_) => "deposit",
+            RequestBody::FetchMail(_) => "fetch_mail",
+            RequestBody::AckMail(_) => "ack_mail",
+            RequestBody::Deregistration(_) => "deregistration",
+        }
+    }
+}
+
+impl Registration {
+    /// the bytes signed by the client. The username is signed too, so that
+    /// only the owner of a UUID can claim a name for it
+    pub fn signing_payload(addr: &str, public_key: &str, ttl: u64,
+                           timestamp: u64, username: Option<&str>) -> String {
+        format!("registration|{}|{}|{}|{}|{}", addr, public_key, ttl, 
+                timestamp, username.unwrap_or(""))
+    }
+}
+
+impl Watch {
+    /// a signed request from `uuid` to watch the peer it looks up
+    ///
+    /// `looked_up`: the queried UUID or username, as sent in the lookup
+    /// `signing_key`: the identity key of `uuid`
+    pub fn new(uuid: Uuid, looked_up: &str, timestamp: u64,
+               signing_key: &SigningKey) -> Watch {
+        let payload = Watch::signing_payload(&uuid, looked_up, timestamp);
+        Watch {
+            uuid,
+            timestamp,
+            signature: crypto::sign(signing_key, &payload),
+        }
+    }
+
+    /// the bytes signed by the client. They name the looked up peer, so a
+    /// captured watch can't be used to watch another one
+    pub fn signing_payload(uuid: &Uuid, looked_up: &str, timestamp: u64) 
+        -> String {
+        format!("watch|{}|{}|{}", uuid, looked_up, timestamp)
+    }
+}
+
+impl Heartbeat {
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, ttl: u64, timestamp: u64) -> String {
+        format!("heartbeat|{}|{}|{}", uuid, ttl, timestamp)
+    }
+}
+
+impl Punch {
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, target: Option<&Uuid>, timestamp: u64)
+        -> String {
+        let target = target.map(Uuid::to_string).unwrap_or_default();
+        format!("punch|{}|{}|{}", uuid, target, timestamp)
+    }
+}
+
+impl Relay {
+    /// a signed request to relay `datagram` from `uuid` to `target`
+    ///
+    /// `signing_key`: the identity key of `uuid`
+    pub fn new(uuid: Uuid, target: Uuid, datagram: &[u8], timestamp: u64,
+               signing_key: &SigningKey) -> Relay {
+        let datagram = BASE64.encode(datagram);
+        let payload = Relay::signing_payload(&uuid, &target, &datagram, 
+                                             timestamp);
+        Relay {
+            uuid,
+            target,
+            datagram,
+            timestamp,
+            signature: crypto::sign(signing_key, &payload),
+        }
+    }
+
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, target: &Uuid, datagram: &str, 
+                           timestamp: u64) -> String {
+        format!("relay|{}|{}|{}|{}", uuid, target, timestamp, datagram)
+    }
+
+    /// the datagram to relay, `None` if it isn't valid base64
+    pub fn decoded_datagram(&self) -> Option<Vec<u8>> {
+        BASE64.decode(&self.datagram).ok()
+    }
+}
+
+impl Deposit {
+    /// a signed request to leave `message` for `target`
+    ///
+    /// `signing_key`: the identity key of `uuid`
+    pub fn new(uuid: Uuid, target: Uuid, message: &[u8], timestamp: u64,
+               signing_key: &SigningKey) -> Deposit {
+        let message = BASE64.encode(message);
+        let payload = Deposit::signing_payload(&uuid, &target, &message, 
+                                               timestamp);
+        Deposit {
+            uuid,
+            target,
+            message,
+            timestamp,
+            signature: crypto::sign(signing_key, &payload),
+        }
+    }
+
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, target: &Uuid, message: &str, 
+                           timestamp: u64) -> String {
+        format!("deposit|{}|{}|{}|{}", uuid, target, timestamp, message)
+    }
+
+    /// the message to leave, `None` if it isn't valid base64
+    pub fn decoded_message(&self) -> Option<Vec<u8>> {
+        BASE64.decode(&self.message).ok()
+    }
+}
+
+impl FetchMail {
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, timestamp: u64) -> String {
+        format!("fetch_mail|{}|{}", uuid, timestamp)
+    }
+}
+
+impl AckMail {
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, ids: &[Uuid], timestamp: u64) 
+        -> String {
+        let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+        format!("ack_mail|{}|{}|{}", uuid, ids.join(","), timestamp)
+    }
+}
+
+impl Deregistration {
+    /// the bytes signed by the client
+    pub fn signing_payload(uuid: &Uuid, timestamp: u64) -> String {
+        format!("deregistration|{}|{}", uuid, timestamp)
+    }
+}
+
+/// the form a username is stored and compared in, `None` if it isn't a valid
+/// username. Usernames are case insensitive and made of `MIN_USERNAME_LEN`
+/// to `MAX_USERNAME_LEN` ascii letters, digits, `_`, `-` and `.`
+pub fn normalize_username(username: &str) -> Option<String> {
+    let valid_len = (MIN_USERNAME_LEN..=MAX_USERNAME_LEN)
+        .contains(&username.len());
+    let valid_chars = username.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid_len && valid_chars {
+        Some(username.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+fn default_ttl() -> u64 {
+    DEFAULT_LEASE_TTL_SECS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static NOW: u64 = 1_000_000;
+
+    fn uuid(n: u128) -> Uuid {
+        Uuid::from_u128(n)
+    }
+
+    fn heartbeat() -> Request {
+        Request::new(RequestBody::Heartbeat(Heartbeat {
+            uuid: uuid(1),
+            ttl: 30,
+            timestamp: NOW,
+            signature: "sig".to_string(),
+        }))
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let mut request = heartbeat();
+        assert_eq!(Request::from_bytes(&request.to_bytes()), 
+                   Ok(request.clone()));
+
+        request.id = Some(Uuid::new_v4());
+        let parsed = Request::from_bytes(&request.to_bytes()).unwrap();
+        assert_eq!(parsed.id, request.id);
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn request_fields_are_tagged_and_flattened() {
+        let json: serde_json::Value = 
+            serde_json::from_slice(&heartbeat().to_bytes()).unwrap();
+        assert_eq!(json["version"], PROTOCOL_VERSION);
+        assert_eq!(json["req_type"], "heartbeat");
+        assert_eq!(json["ttl"], 30);
+        // no id is sent rather than a null one
+        assert!(json.get("id").is_none());
+    }
+
+    #[test]
+    fn unknown_version_is_refused() {
+        let mut json: serde_json::Value = 
+            serde_json::from_slice(&heartbeat().to_bytes()).unwrap();
+        json["version"] = (PROTOCOL_VERSION + 1).into();
+        let bytes = serde_json::to_vec(&json).unwrap();
+        assert_eq!(Request::from_bytes(&bytes), 
+                   Err(ErrorCode::UnsupportedVersion));
+
+        // even if the body wouldn't parse in this version
+        let bytes = br#"{"version": 99, "req_type": "teleport"}"#;
+        assert_eq!(Request::from_bytes(bytes), 
+                   Err(ErrorCode::UnsupportedVersion));
+
+        let bytes = br#"{"req_type": "heartbeat"}"#;
+        assert_eq!(Request::from_bytes(bytes), 
+                   Err(ErrorCode::MalformedRequest));
+    }
+
+    #[test]
+    fn unknown_request_type_is_malformed() {
+        let bytes = br#"{"version": 1, "req_type": "teleport"}"#;
+        assert_eq!(Request::from_bytes(bytes), 
+                   Err(ErrorCode::MalformedRequest));
+        assert_eq!(Request::from_bytes(b"not json"), 
+                   Err(ErrorCode::MalformedRequest));
+    }
+
+    // signatures made by one version have to verify in the next, so the
+    // payloads must not change shape
+    #[test]
+    fn signing_payloads_are_stable() {
+        let peer = "00000000-0000-0000-0000-000000000001";
+        let target = "00000000-0000-0000-0000-000000000002";
+
+        assert_eq!(Registration::signing_payload("127.0.0.1:5000", "pk", 60, 
+                                                 NOW, Some("alice")),
+                   "registration|127.0.0.1:5000|pk|60|1000000|alice");
+        assert_eq!(Registration::signing_payload("127.0.0.1:5000", "pk", 60, 
+                                                 NOW, None),
+                   "registration|127.0.0.1:5000|pk|60|1000000|");
+        assert_eq!(Watch::signing_payload(&uuid(1), "alice", NOW),
+                   format!("watch|{}|alice|1000000", peer));
+        assert_eq!(Heartbeat::signing_payload(&uuid(1), 60, NOW),
+                   format!("heartbeat|{}|60|1000000", peer));
+        assert_eq!(Punch::signing_payload(&uuid(1), Some(&uuid(2)), NOW),
+                   format!("punch|{}|{}|1000000", peer, target));
+        assert_eq!(Punch::signing_payload(&uuid(1), None, NOW),
+                   format!("punch|{}||1000000", peer));
+        assert_eq!(Relay::signing_payload(&uuid(1), &uuid(2), "ZGF0YQ==", NOW),
+                   format!("relay|{}|{}|1000000|ZGF0YQ==", peer, target));
+        assert_eq!(Deposit::signing_payload(&uuid(1), &uuid(2), "ZGF0YQ==", 
+                                            NOW),
+                   format!("deposit|{}|{}|1000000|ZGF0YQ==", peer, target));
+        assert_eq!(FetchMail::signing_payload(&uuid(1), NOW),
+                   format!("fetch_mail|{}|1000000", peer));
+        assert_eq!(AckMail::signing_payload(&uuid(1), &[uuid(2), uuid(3)], 
+                                            NOW),
+                   format!("ack_mail|{}|{},{}|1000000", peer, target,
+                           "00000000-0000-0000-0000-000000000003"));
+        assert_eq!(Deregistration::signing_payload(&uuid(1), NOW),
+                   format!("deregistration|{}|1000000", peer));
+    }
+
+    #[test]
+    fn signed_requests_sign_their_payload() {
+        let identity = crypto::generate_identity();
+        let relay = Relay::new(uuid(1), uuid(2), b"data", NOW, &identity);
+        assert_eq!(relay.datagram, "ZGF0YQ==");
+        assert_eq!(relay.signature, 
+                   crypto::sign(&identity, &Relay::signing_payload(
+                       &uuid(1), &uuid(2), "ZGF0YQ==", NOW)));
+        assert_eq!(relay.decoded_datagram(), Some(b"data".to_vec()));
+
+        let deposit = Deposit::new(uuid(1), uuid(2), b"data", NOW, &identity);
+        assert_eq!(deposit.signature, 
+                   crypto::sign(&identity, &Deposit::signing_payload(
+                       &uuid(1), &uuid(2), "ZGF0YQ==", NOW)));
+
+        let watch = Watch::new(uuid(1), "alice", NOW, &identity);
+        assert_eq!(watch.signature, 
+                   crypto::sign(&identity, &Watch::signing_payload(
+                       &uuid(1), "alice", NOW)));
+    }
+}
@@ -0,0 +1,258 @@
+/*
+ * File: response.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: responses sent by the central index server to clients
+ */
+use std::fmt;
+use std::net::SocketAddr;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::codec::Codec;
+use crate::{check_version, PROTOCOL_VERSION};
+
+/// a response from the central index server
+///
+/// ```text
+/// {
+///     "version"   : 1,
+///     "id"        : "<uuid>",     (the id of the request answered, if any)
+///     "resp_type" : "registered" | "lease_renewed" | "deregistered"
+///                   | "peer_info" | "peer_moved" | "observed" | "punch"
+///                   | "relayed" | "deposited" | "mail" | "mail_acked"
+///                   | "error",
+///     ...fields of the response type
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    /// the id of the request this answers. Not set on pushes, nor on answers
+    /// to requests that had none or couldn't be parsed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(flatten)]
+    pub body: ResponseBody,
+}
+
+/// the different responses sent by the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "resp_type", rename_all = "snake_case")]
+pub enum ResponseBody {
+    /// answers a `Registration` with the UUID and the granted lease
+    Registered { uuid: Uuid, ttl: u64 },
+    /// answers a `Heartbeat` with the granted lease
+    LeaseRenewed { ttl: u64 },
+    /// answers a `Deregistration`
+    Deregistered,
+    /// answers a `Query` or a `LookupByName`. `address` is only set for peers
+    /// that are online, `public_key` and `codecs` for offline ones too, so
+    /// that messages can be left in their mailbox. `address` is the one
+    /// the peer registered, `observed_address` the one its `Punch` requests
+    /// came from, which differ for peers behind a NAT
+    PeerInfo {
+        uuid: Uuid,
+        status: PeerStatus,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        address: Option<SocketAddr>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        observed_address: Option<SocketAddr>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        public_key: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        codecs: Vec<Codec>,
+    },
+    /// pushed to the peers watching `uuid` when its registered or observed
+    /// address changes, e.g. because it restarted on another port
+    PeerMoved {
+        uuid: Uuid,
+        address: SocketAddr,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        observed_address: Option<SocketAddr>,
+    },
+    /// answers a `Punch` without a target with the address it came from
+    Observed { address: SocketAddr },
+    /// sent to both peers of a hole punch, each getting the other's observed
+    /// address. Both then send probes there, so that their NATs let the
+    /// other's traffic in
+    Punch { uuid: Uuid, address: SocketAddr },
+    /// a datagram relayed from the peer `uuid`, base64 encoded. Answers are
+    /// relayed back the same way
+    Relayed { uuid: Uuid, datagram: String },
+    /// answers a `Deposit` with the id the message is kept under. Not named
+    /// `id`, which is the id of the request answered
+    Deposited { mail_id: Uuid, expires_at: u64 },
+    /// answers a `FetchMail` with the oldest messages in the mailbox, and
+    /// how many more are left after them
+    Mail { messages: Vec<MailItem>, remaining: usize },
+    /// answers an `AckMail` with how many messages were removed
+    MailAcked { removed: usize },
+    /// the request was refused
+    Error { code: ErrorCode, message: String },
+}
+
+/// a message left in a mailbox
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MailItem {
+    pub id: Uuid,
+    pub src_uuid: Uuid,         // the peer that left it, as authenticated
+    pub message: String,        // base64 encoded
+    pub deposited_at: u64,
+}
+
+impl MailItem {
+    /// the encoded message, `None` if it isn't valid base64
+    pub fn decoded_message(&self) -> Option<Vec<u8>> {
+        BASE64.decode(&self.message).ok()
+    }
+}
+
+/// whether a queried peer can currently be reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerStatus {
+    Online,
+    /// registered, but its lease ran out
+    Offline,
+    Unknown,
+}
+
+/// why the server refused a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    MalformedRequest,
+    UnsupportedVersion,
+    InvalidTimestamp,
+    InvalidVerifyingKey,
+    InvalidSignature,
+    InvalidAddr,
+    StaleRequest,
+    UuidMismatch,
+    UnknownPeer,
+    InvalidUsername,
+    UsernameTaken,
+    UnknownUsername,
+    RelayDisabled,
+    PeerOnline,
+    MailboxFull,
+    MailQuotaExceeded,
+    StorageError,
+}
+
+impl Response {
+    /// wraps a response body with the current protocol version
+    pub fn new(body: ResponseBody) -> Response {
+        Response { version: PROTOCOL_VERSION, id: None, body }
+    }
+
+    /// an error response, using the code's description as message
+    pub fn error(code: ErrorCode) -> Response {
+        Response::new(ResponseBody::Error {
+            code,
+            message: code.to_string()
+        })
+    }
+
+    /// serializes the response for sending
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // can't fail, every field serializes to json
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// parses a received response
+    pub fn from_bytes(bytes: &[u8]) -> Result<Response, ErrorCode> {
+        let value = check_version(bytes)?;
+        serde_json::from_value(value).map_err(|_| ErrorCode::MalformedRequest)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            ErrorCode::MalformedRequest => "malformed request",
+            ErrorCode::UnsupportedVersion => "unsupported protocol version",
+            ErrorCode::InvalidTimestamp => "invalid timestamp",
+            ErrorCode::InvalidVerifyingKey => "invalid verifying key",
+            ErrorCode::InvalidSignature => "invalid signature",
+            ErrorCode::InvalidAddr => "invalid address",
+            ErrorCode::StaleRequest => "stale or replayed request",
+            ErrorCode::UuidMismatch => "uuid does not match verifying key",
+            ErrorCode::UnknownPeer => "unknown peer",
+            ErrorCode::InvalidUsername => "invalid username",
+            ErrorCode::UsernameTaken => "username belongs to another peer",
+            ErrorCode::UnknownUsername => "no peer with that username",
+            ErrorCode::RelayDisabled => "server does not relay messages",
+            ErrorCode::PeerOnline => "peer is online, send to it directly",
+            ErrorCode::MailboxFull => "recipient's mailbox is full",
+            ErrorCode::MailQuotaExceeded => 
+                "sender has left too much mail already",
+            ErrorCode::StorageError => "server storage failure",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer_info() -> Response {
+        Response::new(ResponseBody::PeerInfo {
+            uuid: Uuid::new_v4(),
+            status: PeerStatus::Offline,
+            address: None,
+            observed_address: None,
+            public_key: Some("pk".to_string()),
+            codecs: vec![Codec::Binary, Codec::Json],
+        })
+    }
+
+    #[test]
+    fn response_round_trips() {
+        let mut response = peer_info();
+        assert_eq!(Response::from_bytes(&response.to_bytes()), 
+                   Ok(response.clone()));
+
+        response.id = Some(Uuid::new_v4());
+        let parsed = Response::from_bytes(&response.to_bytes()).unwrap();
+        assert_eq!(parsed.id, response.id);
+        assert_eq!(parsed, response);
+
+        // the id of the deposited mail doesn't clash with the request id
+        let mut deposited = Response::new(ResponseBody::Deposited {
+            mail_id: Uuid::new_v4(),
+            expires_at: 1_000_000,
+        });
+        deposited.id = Some(Uuid::new_v4());
+        assert_eq!(Response::from_bytes(&deposited.to_bytes()), 
+                   Ok(deposited));
+    }
+
+    #[test]
+    fn error_response_carries_code_and_description() {
+        let response = Response::error(ErrorCode::MailboxFull);
+        let json: serde_json::Value = 
+            serde_json::from_slice(&response.to_bytes()).unwrap();
+        assert_eq!(json["resp_type"], "error");
+        assert_eq!(json["code"], "mailbox_full");
+        assert_eq!(json["message"], "recipient's mailbox is full");
+        assert_eq!(Response::from_bytes(&response.to_bytes()), Ok(response));
+    }
+
+    #[test]
+    fn unknown_version_is_refused() {
+        let mut json: serde_json::Value = 
+            serde_json::from_slice(&peer_info().to_bytes()).unwrap();
+        json["version"] = (PROTOCOL_VERSION + 1).into();
+        let bytes = serde_json::to_vec(&json).unwrap();
+        assert_eq!(Response::from_bytes(&bytes), 
+                   Err(ErrorCode::UnsupportedVersion));
+
+        let bytes = br#"{"version": 1, "resp_type": "teleported"}"#;
+        assert_eq!(Response::from_bytes(bytes), 
+                   Err(ErrorCode::MalformedRequest));
+    }
+}
@@ -0,0 +1,156 @@
+/*
+ * File: settings.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: settings of the client and server binaries, gathered from
+ * the command line, the environment and a config file
+ */
+use std::{collections::HashMap, env, fs, str::FromStr};
+use json::JsonValue;
+
+/// the command line of a binary, along with its config file. Each setting
+/// `name` is looked up as the flag `--name <value>`, then as an environment
+/// variable, then as the key `name` of the config file, a json object:
+///
+/// ```text
+/// { "listen": "::", "port": 6001, "server": "index.example.org:50000" }
+/// ```
+///
+/// The config file is named with `--config <path>`, or by an environment
+/// variable
+#[derive(Debug)]
+pub struct Settings {
+    flags: HashMap<String, String>,
+    file: JsonValue,
+    pub positional: Vec<String>,    // arguments that aren't flags, in order
+}
+
+impl Settings {
+    /// parses the command line and loads the config file, if any. Fails on
+    /// flags and config keys that aren't in `names`, so typos don't go
+    /// unnoticed
+    ///
+    /// `args`: the command line, without the binary's name
+    /// `names`: the settings the binary understands
+    /// `config_env`: environment variable naming the config file
+    pub fn load(args: &[String], names: &[&str], config_env: &str)
+        -> Result<Settings, String> {
+        let mut settings = Settings {
+            flags: HashMap::new(),
+            file: JsonValue::Null,      // no config file, no settings in it
+            positional: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    settings.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            if name != "config" && !names.contains(&name) {
+                return Err(format!("Unknown flag {}", arg));
+            }
+            match args.next() {
+                Some(value) => settings.flags.insert(name.to_string(),
+                                                     value.clone()),
+                None => return Err(format!("{} needs a value", arg)),
+            };
+        }
+
+        let config_path = match settings.flags.get("config") {
+            Some(path) => Some(path.clone()),
+            None => env::var(config_env).ok(),
+        };
+        if let Some(path) = config_path {
+            settings.file = fs::read_to_string(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|contents| json::parse(&contents)
+                          .map_err(|err| err.to_string()))
+                .map_err(|err| format!("Could not read {}: {}", path, err))?;
+            if !settings.file.is_object() {
+                return Err(format!("{} must hold a json object", path));
+            }
+            for (name, _) in settings.file.entries() {
+                if !names.contains(&name) {
+                    return Err(format!("Unknown setting {} in {}", name,
+                                       path));
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    /// a setting, from the command line, the environment variable `env_var`
+    /// or the config file, in that order
+    ///
+    /// `name`: the flag without its `--`, also the config key
+    pub fn get(&self, name: &str, env_var: &str) -> Option<String> {
+        if let Some(value) = self.flags.get(name) {
+            return Some(value.clone());
+        }
+        if let Ok(value) = env::var(env_var) {
+            return Some(value);
+        }
+        match &self.file[name] {
+            JsonValue::Null => None,
+            // numbers are as good as strings, `"port": 6001`
+            value => Some(value.to_string()),
+        }
+    }
+
+    /// a setting parsed into a `T`, `None` if it isn't set anywhere
+    pub fn parse<T: FromStr>(&self, name: &str, env_var: &str)
+        -> Result<Option<T>, String> {
+        match self.get(name, env_var) {
+            Some(value) => match value.parse() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(_) => Err(format!("Invalid {}: {}", name, value)),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_override_the_config_file() {
+        let path = env::temp_dir().join("p2p_settings_test.json");
+        fs::write(&path, r#"{ "port": 6001, "listen": "::1" }"#).unwrap();
+        let path = path.to_str().unwrap();
+
+        let settings = Settings::load(
+            &args(&["--config", path, "--port", "6002", "peers.log"]),
+            &["listen", "port"], "P2P_SETTINGS_TEST_UNSET").unwrap();
+        assert_eq!(settings.parse::<u16>("port", "P2P_SETTINGS_TEST_UNSET"),
+                   Ok(Some(6002)));
+        assert_eq!(settings.get("listen", "P2P_SETTINGS_TEST_UNSET"),
+                   Some("::1".to_string()));
+        assert_eq!(settings.positional, vec!["peers.log".to_string()]);
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        let names = ["port"];
+        assert!(Settings::load(&args(&["--prot", "6001"]), &names,
+                               "P2P_SETTINGS_TEST_UNSET").is_err());
+        assert!(Settings::load(&args(&["--port"]), &names,
+                               "P2P_SETTINGS_TEST_UNSET").is_err());
+
+        let path = env::temp_dir().join("p2p_settings_typo_test.json");
+        fs::write(&path, r#"{ "prot": 6001 }"#).unwrap();
+        let settings = Settings::load(
+            &args(&["--config", path.to_str().unwrap()]), &names,
+            "P2P_SETTINGS_TEST_UNSET");
+        assert!(settings.is_err());
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * File: transfer.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: payloads of the messages used to transfer a file between two
+ * peers, carried as json in the (encrypted) `data` of a `Message`
+ */
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::{Builder, Uuid};
+
+/// bytes of the file carried by each `FileChunk`. Small enough that a chunk
+/// only spans a few datagrams, since losing one retransmits the whole chunk
+pub const CHUNK_SIZE: u64 = 4096;
+
+/// announces a file, sent in a `FileOffer` message. Answered with
+/// `FileReply::Accepted`, telling the sender where to resume
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileOffer {
+    pub transfer_id: Uuid,
+    /// file name without any directories
+    pub name: String,
+    pub size: u64,
+    /// hex encoded SHA-256 of the whole file
+    pub sha256: String,
+    pub chunk_size: u64,
+}
+
+/// a slice of the file, sent in a `FileChunk` message. Answered with
+/// `FileReply::Stored` once it is on the recipient's disk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChunk {
+    pub transfer_id: Uuid,
+    pub index: u64,
+    /// base64 encoded, `Message::data` is a string
+    pub data: String,
+}
+
+/// sent in a `FileEnd` message once every chunk was stored. The recipient
+/// checks the file against the offered hash and answers with the outcome
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEnd {
+    pub transfer_id: Uuid,
+}
+
+/// the answer to a file message, carried in its ack
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub enum FileReply {
+    /// the first `resume_from` chunks are already on disk
+    Accepted { resume_from: u64 },
+    Stored,
+    /// the file matched its hash and was saved
+    Verified,
+    /// the file did not match its hash and was discarded
+    Corrupt,
+    /// `FileEnd` arrived before chunk `missing`
+    Incomplete { missing: u64 },
+    Rejected { reason: String },
+}
+
+impl FileOffer {
+    /// number of chunks the file is sent in
+    pub fn chunk_count(&self) -> u64 {
+        self.size.div_ceil(self.chunk_size)
+    }
+
+    /// size of chunk `index`, which is `chunk_size` except for the last one
+    pub fn chunk_len(&self, index: u64) -> u64 {
+        self.chunk_size.min(self.size - index * self.chunk_size)
+    }
+}
+
+impl FileChunk {
+    pub fn new(transfer_id: Uuid, index: u64, bytes: &[u8]) -> FileChunk {
+        FileChunk { transfer_id, index, data: BASE64.encode(bytes) }
+    }
+
+    /// the decoded slice of the file, `None` if `data` isn't valid base64
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        BASE64.decode(&self.data).ok()
+    }
+}
+
+/// the id of the transfer of a file with hash `sha256` between two peers.
+/// Offering the same file to the same peer again yields the same id, which
+/// is what lets an interrupted transfer resume
+pub fn transfer_id(sha256: &str, src_uuid: &Uuid, dst_uuid: &Uuid) -> Uuid {
+    let mut hasher = Sha256::new();
+    hasher.update(sha256.as_bytes());
+    hasher.update(src_uuid.as_bytes());
+    hasher.update(dst_uuid.as_bytes());
+    let digest = hasher.finalize();
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Builder::from_custom_bytes(bytes).into_uuid()
+}
+
+/// serializes a transfer payload into `Message::data`
+pub fn to_data<T: Serialize>(payload: &T) -> String {
+    // can't fail, every payload serializes to json
+    serde_json::to_string(payload).unwrap()
+}
+
+/// parses a transfer payload out of `Message::data`
+pub fn from_data<'a, T: Deserialize<'a>>(data: &'a str) -> Option<T> {
+    serde_json::from_str(data).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offer(size: u64) -> FileOffer {
+        FileOffer {
+            transfer_id: Uuid::new_v4(),
+            name: "file.bin".to_string(),
+            size,
+            sha256: String::new(),
+            chunk_size: CHUNK_SIZE,
+        }
+    }
+
+    #[test]
+    fn chunks_cover_the_file() {
+        for size in [0, 1, CHUNK_SIZE - 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 5] {
+            let offer = offer(size);
+            let total: u64 = (0..offer.chunk_count())
+                .map(|index| offer.chunk_len(index))
+                .sum();
+            assert_eq!(total, size);
+        }
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let chunk = FileChunk::new(Uuid::new_v4(), 7, &bytes);
+
+        let received: FileChunk = from_data(&to_data(&chunk)).unwrap();
+        assert_eq!(received, chunk);
+        assert_eq!(received.bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn resending_a_file_keeps_its_transfer_id() {
+        let (src_uuid, dst_uuid) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(transfer_id("abc", &src_uuid, &dst_uuid),
+                   transfer_id("abc", &src_uuid, &dst_uuid));
+        assert_ne!(transfer_id("abc", &src_uuid, &dst_uuid),
+                   transfer_id("abd", &src_uuid, &dst_uuid));
+        assert_ne!(transfer_id("abc", &src_uuid, &dst_uuid),
+                   transfer_id("abc", &dst_uuid, &src_uuid));
+    }
+}
@@ -0,0 +1,277 @@
+/*
+ * File: mailbox.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: messages left by peers for offline peers, kept by the server
+ * until they are fetched or expire
+ */
+
+use std::collections::{HashMap, VecDeque};
+use protocol::{ErrorCode, MailItem};
+use uuid::Uuid;
+
+/// most messages waiting for a single recipient
+static MAX_MAILBOX_MESSAGES: usize = 100;
+/// most bytes of (base64 encoded) messages waiting for a single recipient
+static MAX_MAILBOX_BYTES: usize = 256 * 1024;
+/// most messages a single sender may have waiting in a single mailbox, so it
+/// can't fill the mailbox for everybody else
+static MAX_MAILBOX_MESSAGES_PER_SENDER: usize = 25;
+/// most messages and bytes a single sender may have waiting, in every mailbox
+/// together
+static MAX_SENDER_MESSAGES: usize = 100;
+static MAX_SENDER_BYTES: usize = 256 * 1024;
+/// how long a message waits for its recipient before it is dropped
+pub static MAIL_TTL_SECS: u64 = 7 * 24 * 3600;
+/// bytes of (base64 encoded) messages answering a single fetch. At least one
+/// message is sent whatever its size
+static MAIL_BATCH_BYTES: usize = 8 * 1024;
+
+/// a message along with when it expires
+struct StoredMail {
+    item: MailItem,
+    expires_at: u64,
+}
+
+/// messages and bytes a sender has waiting, in every mailbox together
+#[derive(Default)]
+struct SenderUsage {
+    messages: usize,
+    bytes: usize,
+}
+
+/// one mailbox per recipient, oldest message first. Kept in RAM only, so
+/// mail is lost when the server exits
+#[derive(Default)]
+pub struct Mailboxes {
+    mailboxes: HashMap<Uuid, VecDeque<StoredMail>>,
+    senders: HashMap<Uuid, SenderUsage>,
+}
+
+impl Mailboxes {
+    pub fn new() -> Mailboxes {
+        Mailboxes::default()
+    }
+
+    /// leaves a message for `recipient`. Refused with `MailboxFull` if it
+    /// would exceed the recipient's quota, or the sender's share of it, and
+    /// with `MailQuotaExceeded` if it would exceed the sender's quota
+    ///
+    /// `now`: unix time, from which the message expires in `MAIL_TTL_SECS`
+    pub fn deposit(&mut self, recipient: Uuid, item: MailItem, now: u64)
+        -> Result<u64, ErrorCode> {
+        let sender = self.senders.entry(item.src_uuid).or_default();
+        if sender.messages >= MAX_SENDER_MESSAGES
+            || sender.bytes + item.message.len() > MAX_SENDER_BYTES {
+            return Err(ErrorCode::MailQuotaExceeded);
+        }
+
+        let mailbox = self.mailboxes.entry(recipient).or_default();
+        let used_bytes: usize = mailbox.iter()
+            .map(|mail| mail.item.message.len())
+            .sum();
+        let from_sender = mailbox.iter()
+            .filter(|mail| mail.item.src_uuid == item.src_uuid)
+            .count();
+        if mailbox.len() >= MAX_MAILBOX_MESSAGES
+            || used_bytes + item.message.len() > MAX_MAILBOX_BYTES 
+            || from_sender >= MAX_MAILBOX_MESSAGES_PER_SENDER {
+            return Err(ErrorCode::MailboxFull);
+        }
+
+        sender.messages += 1;
+        sender.bytes += item.message.len();
+        let expires_at = now + MAIL_TTL_SECS;
+        mailbox.push_back(StoredMail { item, expires_at });
+        Ok(expires_at)
+    }
+
+    /// the oldest unexpired messages for `recipient`, up to
+    /// `MAIL_BATCH_BYTES`, and how many are left after them. They stay in
+    /// the mailbox until acknowledged
+    pub fn peek(&self, recipient: &Uuid, now: u64) -> (Vec<MailItem>, usize) {
+        let mut batch = Vec::new();
+        let mut batch_bytes = 0;
+        let live_mail = match self.mailboxes.get(recipient) {
+            Some(mailbox) => mailbox.iter()
+                .filter(|mail| now < mail.expires_at)
+                .collect::<Vec<_>>(),
+            None => return (batch, 0),
+        };
+
+        for mail in &live_mail {
+            batch_bytes += mail.item.message.len();
+            if !batch.is_empty() && batch_bytes > MAIL_BATCH_BYTES {
+                break;
+            }
+            batch.push(mail.item.clone());
+        }
+        let remaining = live_mail.len() - batch.len();
+        (batch, remaining)
+    }
+
+    /// removes the messages `ids` from the mailbox of `recipient`, returning
+    /// how many there were
+    pub fn remove(&mut self, recipient: &Uuid, ids: &[Uuid]) -> usize {
+        let mailbox = match self.mailboxes.get_mut(recipient) {
+            Some(mailbox) => mailbox,
+            None => return 0,
+        };
+        let before = mailbox.len();
+        let senders = &mut self.senders;
+        mailbox.retain(|mail| {
+            let keep = !ids.contains(&mail.item.id);
+            if !keep {
+                release(senders, &mail.item);
+            }
+            keep
+        });
+        let removed = before - mailbox.len();
+        if mailbox.is_empty() {
+            self.mailboxes.remove(recipient);
+        }
+        removed
+    }
+
+    /// drops every message that expired by unix time `now`, returning how
+    /// many there were
+    pub fn purge(&mut self, now: u64) -> usize {
+        let mut purged = 0;
+        let senders = &mut self.senders;
+        self.mailboxes.retain(|_, mailbox| {
+            let before = mailbox.len();
+            mailbox.retain(|mail| {
+                let live = now < mail.expires_at;
+                if !live {
+                    release(senders, &mail.item);
+                }
+                live
+            });
+            purged += before - mailbox.len();
+            !mailbox.is_empty()
+        });
+        purged
+    }
+}
+
+/// gives the quota a message used back to its sender
+fn release(senders: &mut HashMap<Uuid, SenderUsage>, item: &MailItem) {
+    if let Some(sender) = senders.get_mut(&item.src_uuid) {
+        sender.messages -= 1;
+        sender.bytes -= item.message.len();
+        if sender.messages == 0 {
+            senders.remove(&item.src_uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static NOW: u64 = 1_000_000;
+
+    fn mail(src_uuid: Uuid, len: usize) -> MailItem {
+        MailItem {
+            id: Uuid::new_v4(),
+            src_uuid,
+            message: "m".repeat(len),
+            deposited_at: NOW,
+        }
+    }
+
+    #[test]
+    fn mail_is_fetched_until_acknowledged() {
+        let mut mailboxes = Mailboxes::new();
+        let (recipient, sender) = (Uuid::new_v4(), Uuid::new_v4());
+        let first = mail(sender, MAIL_BATCH_BYTES);
+        let second = mail(sender, 10);
+        mailboxes.deposit(recipient, first.clone(), NOW).unwrap();
+        mailboxes.deposit(recipient, second.clone(), NOW).unwrap();
+
+        // the batch is full after the first message
+        let (batch, remaining) = mailboxes.peek(&recipient, NOW);
+        assert_eq!(batch.iter().map(|mail| mail.id).collect::<Vec<_>>(),
+                   vec![first.id]);
+        assert_eq!(remaining, 1);
+        assert_eq!(mailboxes.peek(&recipient, NOW).0.len(), 1);
+
+        assert_eq!(mailboxes.remove(&recipient, &[first.id]), 1);
+        let (batch, remaining) = mailboxes.peek(&recipient, NOW);
+        assert_eq!(batch.iter().map(|mail| mail.id).collect::<Vec<_>>(),
+                   vec![second.id]);
+        assert_eq!(remaining, 0);
+        assert_eq!(mailboxes.remove(&recipient, &[first.id, second.id]), 1);
+        assert_eq!(mailboxes.peek(&recipient, NOW), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn expired_mail_is_hidden_then_purged() {
+        let mut mailboxes = Mailboxes::new();
+        let (recipient, sender) = (Uuid::new_v4(), Uuid::new_v4());
+        let expires_at = mailboxes.deposit(recipient, mail(sender, 10), NOW)
+            .unwrap();
+        assert_eq!(expires_at, NOW + MAIL_TTL_SECS);
+
+        assert_eq!(mailboxes.peek(&recipient, expires_at), (Vec::new(), 0));
+        assert_eq!(mailboxes.purge(expires_at - 1), 0);
+        assert_eq!(mailboxes.purge(expires_at), 1);
+        // the sender's quota is given back
+        assert!(mailboxes.senders.is_empty());
+    }
+
+    #[test]
+    fn mailboxes_have_a_quota() {
+        let mut mailboxes = Mailboxes::new();
+        let recipient = Uuid::new_v4();
+        for _ in 0..MAX_MAILBOX_MESSAGES {
+            mailboxes.deposit(recipient, mail(Uuid::new_v4(), 10), NOW)
+                .unwrap();
+        }
+        assert_eq!(mailboxes.deposit(recipient, mail(Uuid::new_v4(), 10), NOW),
+                   Err(ErrorCode::MailboxFull));
+
+        let other = Uuid::new_v4();
+        for _ in 0..2 {
+            mailboxes.deposit(other, mail(Uuid::new_v4(), 
+                                          MAX_MAILBOX_BYTES / 2), NOW)
+                .unwrap();
+        }
+        assert_eq!(mailboxes.deposit(other, mail(Uuid::new_v4(), 1), NOW),
+                   Err(ErrorCode::MailboxFull));
+    }
+
+    #[test]
+    fn senders_have_a_quota() {
+        let mut mailboxes = Mailboxes::new();
+        let sender = Uuid::new_v4();
+
+        // a sender only gets a share of any one mailbox
+        let recipient = Uuid::new_v4();
+        for _ in 0..MAX_MAILBOX_MESSAGES_PER_SENDER {
+            mailboxes.deposit(recipient, mail(sender, 10), NOW).unwrap();
+        }
+        assert_eq!(mailboxes.deposit(recipient, mail(sender, 10), NOW),
+                   Err(ErrorCode::MailboxFull));
+        assert!(mailboxes.deposit(recipient, mail(Uuid::new_v4(), 10), NOW)
+                .is_ok());
+
+        // and can't fill every other mailbox either
+        let mut sent = MAX_MAILBOX_MESSAGES_PER_SENDER;
+        while sent < MAX_SENDER_MESSAGES {
+            mailboxes.deposit(Uuid::new_v4(), mail(sender, 10), NOW).unwrap();
+            sent += 1;
+        }
+        let refused = mail(sender, 10);
+        assert_eq!(mailboxes.deposit(Uuid::new_v4(), refused, NOW),
+                   Err(ErrorCode::MailQuotaExceeded));
+
+        // fetched mail gives the quota back
+        let (batch, _) = mailboxes.peek(&recipient, NOW);
+        let ids: Vec<Uuid> = batch.iter().map(|mail| mail.id).collect();
+        mailboxes.remove(&recipient, &ids);
+        assert!(mailboxes.deposit(Uuid::new_v4(), mail(sender, 10), NOW)
+                .is_ok());
+    }
+}
@@ -5,35 +5,87 @@
  *
  * Description: implementation of central server protocol
  */
+use std::{env, net::{IpAddr, SocketAddr}, path::Path};
+use protocol::{fragment::MAX_UDP_PAYLOAD, DEFAULT_SERVER_PORT};
+use protocol::net::default_listen_ip;
+use protocol::settings::Settings;
 use server::ServerNode;
+use storage::{FileStore, MemoryStore, PeerStore};
+pub mod mailbox;
+pub mod replies;
 pub mod server;
+pub mod storage;
+pub mod watchers;
 
-static DEFAULT_PORT: u16 = 50_000;
+static USAGE: &str = "Try: ./server_protocol [--config <file>] \
+                     [--listen <ip>] [--port <port>] [--relay on|off] \
+                     [peer_log_file]";
 
 /// main routine
-fn main() {
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // e.g. `--listen ::1`, or `P2P_SERVER_LISTEN`, or `"listen"` in the
+    // file named by `--config` or `P2P_SERVER_CONFIG`
+    let settings = match Settings::load(&args, &["listen", "port", "relay"],
+                                        "P2P_SERVER_CONFIG") {
+        Ok(settings) => settings,
+        Err(err) => panic!("{}. {}", err, USAGE),
+    };
+    assert!(settings.positional.len() <= 1, "{}", USAGE);
+
+    // every interface by default, so that peers on other hosts can reach us
+    // over either IPv4 or IPv6
+    let listen_ip: IpAddr = settings.parse("listen", "P2P_SERVER_LISTEN")
+        .expect(USAGE)
+        .unwrap_or_else(default_listen_ip);
+    let port: u16 = settings.parse("port", "P2P_SERVER_PORT")
+        .expect(USAGE)
+        .unwrap_or(DEFAULT_SERVER_PORT);
+
+    // the index only survives restarts if it is backed by a log on disk
+    let store: Box<dyn PeerStore> = match settings.positional.first() {
+        Some(path) => match FileStore::open(Path::new(path)) {
+            Ok(store) => Box::new(store),
+            Err(err) => panic!("Error opening peer log. {}", err),
+        },
+        None => Box::new(MemoryStore::new()),
+    };
 
     // init server
-    let mut server = match ServerNode::build(DEFAULT_PORT){
+    let listen_addr = SocketAddr::new(listen_ip, port);
+    let mut server = match ServerNode::build(listen_addr, store).await {
         Ok(server_node) => server_node,
-        Err(_) => panic!("oops"),
+        Err(err) => panic!("Error starting server. {}", err),
     };
 
+    // relaying costs the server bandwidth, `--relay off` leaves peers that
+    // can't reach each other on their own
+    server.relay = settings.get("relay", "P2P_RELAY")
+        .is_none_or(|relay| relay != "off");
+
     println!("=====Initializing server=====");
     println!("listening at: {}", server.listening_socket.local_addr().unwrap());
     println!();
 
-    let mut recv_buf = [0; 1024];
+    // evicts peers whose lease ran out, runs for as long as the server does
+    let _sweeper = server.spawn_sweeper();
+    // flushes the peer log to disk in batches, off the request handlers
+    let _syncer = server.spawn_syncer();
+
+    // sized for the largest datagram, so no request is silently truncated
+    let mut recv_buf = vec![0; MAX_UDP_PAYLOAD];
     loop {
-        match server.listening_socket.recv_from(&mut recv_buf) {
-            Ok((n_bytes, src_addr)) => {
-                let recv_data = &recv_buf[..n_bytes];
-                match server.handle_request(recv_data, src_addr) {
-                    Ok(_) => {},
-                    Err(_) => {}
-                }
-            } 
-            Err(_) => {}, // don't do anything on error
+        // don't do anything on error
+        if let Ok((n_bytes, src_addr)) = server.listening_socket
+            .recv_from(&mut recv_buf).await {
+            // every request gets its own task, so requests are handled 
+            // concurrently and the loop goes straight back to receiving
+            let recv_data = recv_buf[..n_bytes].to_vec();
+            let server = server.clone();
+            tokio::spawn(async move {
+                let _ = server.handle_request(&recv_data, src_addr).await;
+            });
         }
     }
 }
@@ -0,0 +1,129 @@
+/*
+ * File: replies.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: responses recently sent, kept so that a request the client
+ * retransmits is answered again instead of being handled twice
+ */
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use protocol::Response;
+use uuid::Uuid;
+
+/// how long a response is kept. Clients give up on a request well before
+static REPLY_TTL_SECS: u64 = 30;
+/// most responses kept at once. The oldest make room for new ones, so a flood
+/// of requests can't grow the server without bound
+static MAX_REPLIES: usize = 4096;
+
+/// a response along with who it went to and when it is dropped
+struct StoredReply {
+    addr: SocketAddr,
+    response: Response,
+    expires_at: u64,
+}
+
+/// responses by the id of the request they answer. Kept in RAM only
+#[derive(Default)]
+pub struct RecentReplies {
+    replies: HashMap<Uuid, StoredReply>,
+    order: VecDeque<Uuid>,      // ids of the kept responses, oldest first
+}
+
+impl RecentReplies {
+    pub fn new() -> RecentReplies {
+        RecentReplies::default()
+    }
+
+    /// keeps the response to the request `id` from `addr`
+    ///
+    /// `now`: unix time, from which the response is kept `REPLY_TTL_SECS`
+    pub fn insert(&mut self, id: Uuid, addr: SocketAddr, response: Response,
+                  now: u64) {
+        let expires_at = now + REPLY_TTL_SECS;
+        let reply = StoredReply { addr, response, expires_at };
+        if self.replies.insert(id, reply).is_some() {
+            return;
+        }
+        self.order.push_back(id);
+        while self.order.len() > MAX_REPLIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+    }
+
+    /// the response to the request `id`, if it was answered recently. Only
+    /// handed to the address that sent the request, so that a captured id
+    /// doesn't give away somebody else's response
+    pub fn get(&self, id: &Uuid, addr: &SocketAddr, now: u64) 
+        -> Option<Response> {
+        self.replies.get(id)
+            .filter(|reply| reply.addr == *addr && now < reply.expires_at)
+            .map(|reply| reply.response.clone())
+    }
+
+    /// drops every response that expired by unix time `now`
+    pub fn purge(&mut self, now: u64) {
+        self.replies.retain(|_, reply| now < reply.expires_at);
+        let replies = &self.replies;
+        self.order.retain(|id| replies.contains_key(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::ResponseBody;
+
+    static NOW: u64 = 1_000_000;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn response() -> Response {
+        Response::new(ResponseBody::Deregistered)
+    }
+
+    #[test]
+    fn replies_are_only_given_to_the_requester() {
+        let mut replies = RecentReplies::new();
+        let id = Uuid::new_v4();
+        replies.insert(id, addr(6001), response(), NOW);
+
+        assert!(replies.get(&id, &addr(6001), NOW).is_some());
+        assert!(replies.get(&id, &addr(6002), NOW).is_none());
+        assert!(replies.get(&Uuid::new_v4(), &addr(6001), NOW).is_none());
+    }
+
+    #[test]
+    fn replies_expire() {
+        let mut replies = RecentReplies::new();
+        let id = Uuid::new_v4();
+        replies.insert(id, addr(6001), response(), NOW);
+
+        let expires_at = NOW + REPLY_TTL_SECS;
+        assert!(replies.get(&id, &addr(6001), expires_at - 1).is_some());
+        assert!(replies.get(&id, &addr(6001), expires_at).is_none());
+        replies.purge(expires_at);
+        assert!(replies.replies.is_empty() && replies.order.is_empty());
+    }
+
+    #[test]
+    fn oldest_replies_make_room() {
+        let mut replies = RecentReplies::new();
+        let ids: Vec<Uuid> = (0..MAX_REPLIES + 1).map(|_| Uuid::new_v4())
+            .collect();
+        for id in &ids {
+            replies.insert(*id, addr(6001), response(), NOW);
+        }
+
+        assert_eq!(replies.replies.len(), MAX_REPLIES);
+        assert!(replies.get(&ids[0], &addr(6001), NOW).is_none());
+        assert!(replies.get(&ids[1], &addr(6001), NOW).is_some());
+        assert!(replies.get(&ids[MAX_REPLIES], &addr(6001), NOW).is_some());
+    }
+}
@@ -6,159 +6,961 @@
  * Description: implementation of central server protocol
  */
 
-use std::net::{UdpSocket, SocketAddr};
-use std::collections::HashMap;
-use json;
-use uuid::Uuid;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ed25519_dalek::{Signature, VerifyingKey};
+use protocol::{
+    AckMail, Codec, Deposit, Deregistration, ErrorCode, FetchMail, Heartbeat, 
+    LookupByName, MailItem, PeerStatus, Punch, Query, Registration, Relay, 
+    Request, RequestBody, Response, ResponseBody, Watch,
+};
+use protocol::net::{bind_udp, canonical, reachable_from};
+use protocol::request::{normalize_username, MAX_DEPOSIT_LEN, MAX_RELAYED_LEN};
+use tokio::{net::UdpSocket, task::{self, JoinHandle}, time};
+use sha2::{Digest, Sha256};
+use uuid::{Builder, Uuid};
+use crate::mailbox::Mailboxes;
+use crate::replies::RecentReplies;
+use crate::storage::PeerStore;
+use crate::watchers::Watchers;
 
 /// a peer in the network
+#[derive(Clone, PartialEq)]
 pub struct PeerNode {
     pub id: String,
     pub addr: SocketAddr,
+    pub public_key: String,     // hex encoded, used by peers to encrypt messages
+    pub verifying_key: String,  // hex encoded, proves ownership of `id`
+    pub last_timestamp: u64,    // of the last accepted signed request, in ms
+    pub expires_at: u64,        // unix time at which the lease runs out
+    pub codecs: Vec<Codec>,     // message codecs the peer understands
+    pub username: Option<String>,   // unique handle, normalized
+    pub observed_addr: Option<SocketAddr>,  // where its punches come from
 }
 
-/// server node that serves IP requests
+impl PeerNode {
+    /// whether the peer's lease is still running at unix time `now`
+    pub fn is_online(&self, now: u64) -> bool {
+        now < self.expires_at
+    }
+}
+
+/// server node that serves IP requests. Cloning it shares the socket and
+/// the index, so every request can be handled by its own task
+#[derive(Clone)]
 pub struct ServerNode {
-    pub listening_socket: UdpSocket,        // socket that the server listens on
-    peers: HashMap<String, PeerNode>,   // map of peers
+    pub listening_socket: Arc<UdpSocket>,       // socket the server listens on
+    listen_addr: SocketAddr,                    // where it is bound
+    peers: Arc<RwLock<Box<dyn PeerStore>>>,     // index of peers
+    mailboxes: Arc<Mutex<Mailboxes>>,           // mail for offline peers
+    watchers: Arc<Mutex<Watchers>>,             // told when peers move
+    replies: Arc<Mutex<RecentReplies>>,         // answers to retransmissions
+    pub relay: bool,                            // whether to relay messages
+}
+
+/// where the request being handled came from, and the id it was sent with
+#[derive(Clone, Copy)]
+struct Requester {
+    addr: SocketAddr,
+    id: Option<Uuid>,
+}
+
+tokio::task_local! {
+    /// set for as long as a request is handled, so that responses to it
+    /// carry its id without every handler passing it around
+    static REQUESTER: Requester;
 }
 
 /// minimum port number that server listens on
 static MIN_PORT_NUMBER: u16 = 50_000;
+/// signed requests whose timestamp is further than this from the server's
+/// clock are refused, which bounds how long a captured request can be replayed
+static MAX_CLOCK_SKEW_MILLIS: u64 = 60_000;
+/// bounds on the lease a client may ask for
+static MIN_LEASE_TTL_SECS: u64 = 10;
+static MAX_LEASE_TTL_SECS: u64 = 3600;
+/// peers whose lease expired are reported offline for this long before they
+/// are evicted from the index altogether
+static OFFLINE_RETENTION_SECS: u64 = 24 * 3600;
+/// how often the sweeper looks for peers to evict
+static SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// how often changes to the index are flushed to disk, i.e. how many of them
+/// a crash may lose
+static SYNC_INTERVAL: Duration = Duration::from_secs(1);
 
 /// implementations for ServerNode
 impl ServerNode {
     /// inits a ServerNode and returns it
-    /// `port`: the port number that the server will listen on
-    pub fn build(port: u16) -> Result<ServerNode, NodeError> {
-        if port < MIN_PORT_NUMBER {
+    /// `listen_addr`: the interface and port that the server will listen on
+    /// `store`: where the peer index is kept
+    pub async fn build(listen_addr: SocketAddr, store: Box<dyn PeerStore>) 
+        -> Result<ServerNode, NodeError> {
+        if listen_addr.port() < MIN_PORT_NUMBER {
             let err_msg = String::from("Select higher port number");
             return Err(NodeError::NodeCreationError(err_msg));
         }
 
-        let listen_addr = SocketAddr::from(([127, 0, 0, 1], port));
-        let socket = match UdpSocket::bind(listen_addr) {
+        // dual-stack when bound to `::`, so that IPv4 and IPv6 peers meet
+        let socket = match bind_udp(listen_addr)
+            .and_then(UdpSocket::from_std) {
             Ok(udp_sock) => udp_sock,
-            Err(_) => {
-                let err_msg = String::from("Error binding socket");
+            Err(err) => {
+                let err_msg = format!("Error binding {}: {}", listen_addr, 
+                                      err);
                 return Err(NodeError::NodeCreationError(err_msg));
             }
         };
 
-        Ok( ServerNode{ listening_socket: socket, peers: HashMap::new() } )
+        Ok( ServerNode{ 
+            listening_socket: Arc::new(socket), 
+            listen_addr,
+            peers: Arc::new(RwLock::new(store)),
+            mailboxes: Arc::new(Mutex::new(Mailboxes::new())),
+            watchers: Arc::new(Mutex::new(Watchers::new())),
+            replies: Arc::new(Mutex::new(RecentReplies::new())),
+            relay: true,
+        } )
+    }
+
+    /// adds a registering peer into the index, returning the copy it 
+    /// replaced. Refused if the registration isn't newer than the last
+    /// request accepted from the peer, or if the peer's username belongs to
+    /// another peer. Both are checked under the same lock as the insertion,
+    /// so that two copies of a registration can't both pass and two peers
+    /// can't claim a name at once
+    pub fn register_peer(&self, peer: PeerNode) 
+        -> Result<Option<PeerNode>, ErrorCode> {
+        let mut peers = self.peers.write().unwrap();
+        let known_peer = peers.get(&peer.id);
+        if let Some(known_peer) = &known_peer {
+            accept_timestamp(known_peer, peer.last_timestamp)?;
+        }
+        let owner = peer.username.as_deref()
+            .and_then(|username| peers.get_by_username(username));
+        if owner.is_some_and(|owner| owner.id != peer.id) {
+            return Err(ErrorCode::UsernameTaken);
+        }
+        peers.insert(peer).map_err(|_| ErrorCode::StorageError)?;
+        Ok(known_peer)
+    }
+
+    /// applies `change` to the peer `id` and stores the result. The peer is
+    /// read and written under the same lock, so no other request changes it
+    /// in between. Returns the peer as changed, or `None` if `change` left it
+    /// as it was, in which case nothing is written
+    ///
+    /// `change`: updates the peer, or refuses to with the reason why
+    pub fn update_peer(&self, id: &str, 
+                       change: impl FnOnce(&mut PeerNode) 
+                           -> Result<(), ErrorCode>)
+        -> Result<Option<PeerNode>, ErrorCode> {
+        let mut peers = self.peers.write().unwrap();
+        // evicted peers have to register again
+        let known_peer = peers.get(id).ok_or(ErrorCode::UnknownPeer)?;
+        let mut peer = known_peer.clone();
+        change(&mut peer)?;
+        if peer == known_peer {
+            return Ok(None);
+        }
+        peers.insert(peer.clone()).map_err(|_| ErrorCode::StorageError)?;
+        Ok(Some(peer))
+    }
+
+    /// removes a peer from the index, returning it if it was present
+    pub fn remove_peer(&self, id: &str) 
+        -> Result<Option<PeerNode>, NodeError> {
+        self.peers.write().unwrap().remove(id)
+    }
+
+    /// looks a PeerNode up based on ID. Lookups only take a read lock, so
+    /// they run concurrently with each other
+    pub fn lookup_id(&self, id: &str) -> Option<PeerNode> {
+        self.peers.read().unwrap().get(id)
     }
 
-    /// adds a peer into the index
-    pub fn add_peer(&mut self, peer: PeerNode) {
-        // this introduces overhead - maybe should just store UUID <-> IP map
-        // .clone() may be inefficient
-        self.peers.insert(peer.id.clone(), peer);
+    /// looks a PeerNode up based on its (normalized) username
+    pub fn lookup_username(&self, username: &str) -> Option<PeerNode> {
+        self.peers.read().unwrap().get_by_username(username)
     }
 
-    /// looks a PeerNode up based on ID
-    pub fn lookup_id(&self, id: &str) -> Option<&PeerNode> {
-        self.peers.get(id)
+    /// spawns a task that periodically evicts peers whose lease expired
+    /// more than `OFFLINE_RETENTION_SECS` ago, and drops expired mail,
+    /// watches and old responses
+    pub fn spawn_sweeper(&self) -> JoinHandle<()> {
+        let peers = Arc::clone(&self.peers);
+        let mailboxes = Arc::clone(&self.mailboxes);
+        let watchers = Arc::clone(&self.watchers);
+        let replies = Arc::clone(&self.replies);
+        tokio::spawn(async move {
+            let mut interval = time::interval(SWEEP_INTERVAL);
+            loop {
+                interval.tick().await;
+                let cutoff = 
+                    unix_timestamp().saturating_sub(OFFLINE_RETENTION_SECS);
+                let evicted = peers.write().unwrap()
+                    .retain(&|peer| peer.expires_at > cutoff);
+                match evicted {
+                    Ok(ids) => for id in ids {
+                        println!("\t\x1b[1mpeer evicted\x1b[0m: UUID = {}", 
+                                 id);
+                    },
+                    Err(err) => 
+                        println!("\t\x1b[31msweep failed\x1b[0m: {}", err),
+                }
+                let purged = mailboxes.lock().unwrap()
+                    .purge(unix_timestamp());
+                if purged > 0 {
+                    println!("\t\x1b[1mmail expired\x1b[0m: {} messages", 
+                             purged);
+                }
+                watchers.lock().unwrap().purge(unix_timestamp());
+                replies.lock().unwrap().purge(unix_timestamp());
+            }
+        })
     }
 
-    pub fn handle_request(&mut self, recv_bytes: &[u8], src_addr: SocketAddr) 
-        -> Result<(), ()> {
+    /// spawns a task that flushes the changes made to the index to disk every
+    /// `SYNC_INTERVAL`, if it is backed by a log. Handling a request doesn't
+    /// wait for the disk then, and the flush happens in a blocking task of
+    /// its own, without holding the index
+    pub fn spawn_syncer(&self) -> JoinHandle<()> {
+        let peers = Arc::clone(&self.peers);
+        tokio::spawn(async move {
+            let mut interval = time::interval(SYNC_INTERVAL);
+            loop {
+                interval.tick().await;
+                let log = match peers.write().unwrap().unsynced_log() {
+                    Ok(Some(log)) => log,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        println!("\t\x1b[31msync failed\x1b[0m: {}", err);
+                        continue;
+                    },
+                };
+                let synced = task::spawn_blocking(move || log.sync_data())
+                    .await;
+                if let Ok(Err(err)) = synced {
+                    println!("\t\x1b[31msync failed\x1b[0m: {}", err);
+                }
+            }
+        })
+    }
 
-        let recv_string = String::from_utf8(recv_bytes.to_vec()).unwrap();
+    /// handles a single datagram. Called from its own task for every request,
+    /// so a malformed request only affects its sender
+    pub async fn handle_request(&self, recv_bytes: &[u8], 
+                                src_addr: SocketAddr) -> Result<(), NodeError> {
+        // IPv4 peers of a dual-stack socket are recorded as IPv4 addresses
+        let src_addr = canonical(src_addr);
 
         println!("===== \x1b[36mrequest from: {:?}\x1b[0m =====", src_addr);
-        println!("\tRequest Size: {}B", recv_string.len());
-        let json_req = match json::parse(&recv_string) {
-            Ok(valid_json) => valid_json,
-            Err(_) => {
-                println!("\t\x1b[31mInvalid JSON\x1b[0m");
-                return Err(())
-            }
+        println!("\tRequest Size: {}B", recv_bytes.len());
+
+        let request = match Request::from_bytes(recv_bytes) {
+            Ok(request) => request,
+            Err(code) => return self.refuse(src_addr, code).await,
         };
 
-        let req_type = &json_req["req_type"];
-        println!("\thandling {} request. src = {}", req_type.to_string(), 
-                 src_addr);
-        if req_type.to_string() == "registration" {
-            return self.handle_registration(src_addr, &json_req);
-        } 
-        else if req_type.to_string() == "query" {
-            return self.handle_lookup(json_req, src_addr);
+        // a retransmission of a request that was answered already gets the
+        // same answer, rather than being refused as a replay or deposited
+        // twice
+        let reply = request.id.and_then(|id| self.replies.lock().unwrap()
+                                        .get(&id, &src_addr, unix_timestamp()));
+        if let Some(reply) = reply {
+            println!("\tanswering retransmitted {} request again\n", 
+                     request.body.name());
+            self.respond(src_addr, &reply).await;
+            return Ok(());
         }
 
+        println!("\thandling {} request. src = {}", request.body.name(), 
+                 src_addr);
+        let requester = Requester { addr: src_addr, id: request.id };
+        let result = REQUESTER.scope(requester, async {
+            match request.body {
+                RequestBody::Registration(req) => 
+                    self.handle_registration(src_addr, req).await,
+                RequestBody::Query(req) => 
+                    self.handle_lookup(src_addr, req).await,
+                RequestBody::LookupByName(req) => 
+                    self.handle_lookup_by_name(src_addr, req).await,
+                RequestBody::Heartbeat(req) => 
+                    self.handle_heartbeat(src_addr, req).await,
+                RequestBody::Punch(req) => 
+                    self.handle_punch(src_addr, req).await,
+                RequestBody::Relay(req) => 
+                    self.handle_relay(src_addr, req).await,
+                RequestBody::Deposit(req) => 
+                    self.handle_deposit(src_addr, req).await,
+                RequestBody::FetchMail(req) => 
+                    self.handle_fetch_mail(src_addr, req).await,
+                RequestBody::AckMail(req) => 
+                    self.handle_ack_mail(src_addr, req).await,
+                RequestBody::Deregistration(req) => 
+                    self.handle_deregistration(src_addr, req).await,
+            }
+        }).await;
+
         // breathing room for the logs
         println!();
 
-        Ok(())
+        result
     }
 
     /// handles a lookup request, and sends a response
     ///
-    /// `bytes`: the received request
+    /// `status` is `online` with the peer's address and public key, `offline`
+    /// if its lease ran out, or `unknown`. Offline peers have their address
+    /// withheld.
+    ///
     /// `src_addr`: the requester's IP
-    pub fn handle_lookup(&self, json_req: json::JsonValue, 
-                         src_addr: SocketAddr) -> Result<(), ()> {
-        let queried_uuid = json_req["queried_uuid"].as_str();
-        let response = match queried_uuid {
-            Some(uuid) => {
-                let mut data = json::JsonValue::new_object();
-                data["address"] = match self.lookup_id(uuid) {
-                    Some(val) => json::from(val.addr.to_string()),
-                    None => json::from("nil")
-                };
-                data["uuid"] = json::from(uuid);
-                data
+    /// `req`: the received request
+    pub async fn handle_lookup(&self, src_addr: SocketAddr, req: Query) 
+        -> Result<(), NodeError> {
+        let uuid = req.queried_uuid;
+        if let Some(watch) = req.watch {
+            self.add_watch(&watch, uuid, &uuid.to_string());
+        }
+        let response = peer_info(uuid, self.lookup_id(&uuid.to_string()));
+        // send response
+        self.respond(src_addr, &Response::new(response)).await;
+        Ok(())
+    }
+
+    /// handles a lookup by username, and sends a response. Answered like a
+    /// lookup of the UUID holding the username, or with an `unknown_username`
+    /// error if nobody holds it
+    ///
+    /// `src_addr`: the requester's IP
+    /// `req`: the received request
+    pub async fn handle_lookup_by_name(&self, src_addr: SocketAddr, 
+                                       req: LookupByName) 
+        -> Result<(), NodeError> {
+        let peer = normalize_username(&req.username)
+            .and_then(|username| self.lookup_username(&username));
+        let response = match peer {
+            Some(peer) => match peer.id.parse::<Uuid>() {
+                Ok(uuid) => {
+                    if let Some(watch) = req.watch {
+                        self.add_watch(&watch, uuid, &req.username);
+                    }
+                    Response::new(peer_info(uuid, Some(peer)))
+                },
+                Err(_) => Response::error(ErrorCode::StorageError),
             },
-            None => {
-                let mut data = json::JsonValue::new_object();
-                data["address"] = json::from("nil");
-                data["id"] = json::from("invalid_uuid");
-                data
-            }
+            None => Response::error(ErrorCode::UnknownUsername),
         };
-        // send response
-        self.listening_socket.send_to(response.dump().as_bytes(), src_addr)
-            .unwrap();
+        self.respond(src_addr, &response).await;
         Ok(())
     }
 
     /// handles a client registering with the server. Adds its address to peer
     /// Map
     ///
-    /// `json_req`: a json request
+    /// The request is signed with the client's ed25519 identity key, and the
+    /// UUID is derived from that key. Registering again with the same key
+    /// rebinds the UUID to the new address, whereas nobody else can produce a
+    /// valid signature for it. A returning client may claim its UUID with the
+    /// optional `uuid` field, which must match the one derived from its key.
+    ///
+    /// The client may also claim a `username`, which is refused if another
+    /// peer holds it. The username is signed along with the rest, so it is
+    /// tied to the identity key just like the UUID.
+    ///
     /// `src_addr`: the requesting addr
-    pub fn handle_registration(&mut self, src_addr: SocketAddr, 
-                               req: &json::JsonValue) -> Result<(), ()> {
-        // init a new peer and insert it
-        let new_uuid = Uuid::new_v4().to_string();
+    /// `req`: the received request
+    pub async fn handle_registration(&self, src_addr: SocketAddr, 
+                                     req: Registration) 
+        -> Result<(), NodeError> {
+        let verifying_key = match parse_verifying_key(&req.verifying_key) {
+            Some(key) => key,
+            None => return self.refuse(src_addr, 
+                                       ErrorCode::InvalidVerifyingKey).await,
+        };
+
+        let payload = Registration::signing_payload(&req.addr, &req.public_key,
+                                                    req.ttl, req.timestamp,
+                                                    req.username.as_deref());
+        if !verify_signature(&verifying_key, &payload, &req.signature) {
+            return self.refuse(src_addr, ErrorCode::InvalidSignature).await;
+        }
+
+        if !is_fresh(req.timestamp) {
+            return self.refuse(src_addr, ErrorCode::StaleRequest).await;
+        }
+
+        let addr = match req.addr.parse::<SocketAddr>() {
+            Ok(addr) => canonical(addr),
+            Err(_) => return self.refuse(src_addr, ErrorCode::InvalidAddr).await,
+        };
 
-        // assumes that the client sends a valid address.
-        let addr = req["addr"].to_string().parse::<SocketAddr>().unwrap();
+        let username = match req.username.as_deref().map(normalize_username) {
+            Some(Some(username)) => Some(username),
+            Some(None) => return self.refuse(src_addr, 
+                                             ErrorCode::InvalidUsername).await,
+            None => None,
+        };
 
-        // I want to avoid the new_uuid.clone() here if possible
-        let new_peer = PeerNode { addr, id: new_uuid.clone() }; 
+        let uuid = derive_uuid(&verifying_key);
 
-        println!("\t\x1b[1mpeer added\x1b[0m: UUID = {}, ADDR = {}", new_peer.id, 
-                 new_peer.addr);
-        self.add_peer(new_peer);
+        // a returning client names the UUID it expects to get back
+        if let Some(claimed_uuid) = req.uuid {
+            if claimed_uuid != uuid {
+                return self.refuse(src_addr, ErrorCode::UuidMismatch).await;
+            }
+        }
 
+        let ttl = clamp_ttl(req.ttl);
+        let new_peer = PeerNode { 
+            addr, 
+            id: uuid.to_string(), 
+            public_key: req.public_key, 
+            verifying_key: req.verifying_key,
+            last_timestamp: req.timestamp,
+            expires_at: unix_timestamp() + ttl,
+            codecs: req.codecs,
+            username,
+            observed_addr: Some(src_addr),
+        }; 
 
-        let mut response = json::JsonValue::new_object();
-        response["status"] = json::JsonValue::from("OK");
-        response["uuid"] = json::JsonValue::from(new_uuid);
+        // a replayed registration can't be newer than the last one accepted
+        let known_peer = match self.register_peer(new_peer.clone()) {
+            Ok(known_peer) => known_peer,
+            Err(code) => return self.refuse(src_addr, code).await,
+        };
+        let action = if known_peer.is_some() { "updated" } else { "added" };
+        println!("\t\x1b[1mpeer {}\x1b[0m: UUID = {}, ADDR = {}, NAME = {}", 
+                 action, new_peer.id, new_peer.addr, 
+                 new_peer.username.as_deref().unwrap_or("-"));
+        let moved = known_peer.is_some_and(|peer| peer.addr != new_peer.addr);
 
         // send response
-        self.listening_socket.send_to(response.dump().as_bytes(), src_addr)
-            .unwrap();
+        let response = ResponseBody::Registered { uuid, ttl };
+        self.respond(src_addr, &Response::new(response)).await;
+        if moved {
+            self.notify_watchers(uuid, &new_peer).await;
+        }
+        Ok(())
+    }
+
+    /// handles a heartbeat, which renews the lease of a registered peer. Must
+    /// be signed by the identity key the peer registered with.
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_heartbeat(&self, src_addr: SocketAddr, req: Heartbeat)
+        -> Result<(), NodeError> {
+        let payload = Heartbeat::signing_payload(&req.uuid, req.ttl, 
+                                                 req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        let ttl = clamp_ttl(req.ttl);
+        let renewed = self.update_peer(&req.uuid.to_string(), |peer| {
+            accept_timestamp(peer, req.timestamp)?;
+            peer.last_timestamp = req.timestamp;
+            peer.expires_at = unix_timestamp() + ttl;
+            Ok(())
+        });
+        if let Err(code) = renewed {
+            return self.refuse(src_addr, code).await;
+        }
+        println!("\t\x1b[1mlease renewed\x1b[0m: UUID = {}, TTL = {}s", 
+                 req.uuid, ttl);
+
+        let response = ResponseBody::LeaseRenewed { ttl };
+        self.respond(src_addr, &Response::new(response)).await;
+        Ok(())
+    }
+
+    /// handles a punch. Records the address it came from as the peer's
+    /// observed address, and answers with it if there is no `target`.
+    /// Otherwise both peers are sent each other's observed address, so that
+    /// they send probes to each other at the same time and each one's NAT
+    /// lets the other's traffic in.
+    ///
+    /// Punches are signed, but only checked for freshness and not against
+    /// the last accepted timestamp, since a client sends them alongside other
+    /// requests, which may overtake them on the way. A replayed punch can at
+    /// worst point the observed address at the replayer, which a NAT
+    /// rewriting the source of every datagram makes impossible to rule out
+    /// anyway.
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_punch(&self, src_addr: SocketAddr, req: Punch)
+        -> Result<(), NodeError> {
+        let payload = Punch::signing_payload(&req.uuid, req.target.as_ref(),
+                                             req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        // only the observed address is touched, whatever else other requests
+        // change in the meantime
+        let observed = self.update_peer(&req.uuid.to_string(), |peer| {
+            peer.observed_addr = Some(src_addr);
+            Ok(())
+        });
+        match observed {
+            Ok(Some(peer)) => {
+                println!("\t\x1b[1mpeer observed\x1b[0m: UUID = {}, \
+                         ADDR = {}", req.uuid, src_addr);
+                self.notify_watchers(req.uuid, &peer).await;
+            },
+            Ok(None) => (),
+            Err(code) => return self.refuse(src_addr, code).await,
+        }
+
+        let target_uuid = match req.target {
+            Some(target_uuid) => target_uuid,
+            None => {
+                let response = ResponseBody::Observed { address: src_addr };
+                self.respond(src_addr, &Response::new(response)).await;
+                return Ok(());
+            }
+        };
+
+        let target_addr = self.lookup_id(&target_uuid.to_string())
+            .filter(|target| target.is_online(unix_timestamp()))
+            .and_then(|target| target.observed_addr);
+        let target_addr = match target_addr {
+            Some(target_addr) => target_addr,
+            None => return self.refuse(src_addr, ErrorCode::UnknownPeer).await,
+        };
+
+        println!("\t\x1b[1mpunching\x1b[0m: {} <-> {}", src_addr, target_addr);
+        let to_target = ResponseBody::Punch { uuid: req.uuid, 
+                                              address: src_addr };
+        let to_requester = ResponseBody::Punch { uuid: target_uuid, 
+                                                 address: target_addr };
+        self.push(target_addr, &Response::new(to_target)).await;
+        self.push(src_addr, &Response::new(to_requester)).await;
+        Ok(())
+    }
+
+    /// handles a datagram that a peer can't deliver to another one directly.
+    /// It is forwarded to the target's observed address, where the target's
+    /// punches keep its NAT open towards the server, or to its registered
+    /// address if it never punched. Nothing is sent back on success, the
+    /// target's ack comes back relayed the same way.
+    ///
+    /// Like punches, relay requests are only checked for freshness, since a
+    /// client relays several datagrams per second. A replayed datagram is 
+    /// dropped by the target as a duplicate.
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_relay(&self, src_addr: SocketAddr, req: Relay)
+        -> Result<(), NodeError> {
+        if !self.relay {
+            return self.refuse(src_addr, ErrorCode::RelayDisabled).await;
+        }
+
+        let payload = Relay::signing_payload(&req.uuid, &req.target, 
+                                             &req.datagram, req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        let datagram_len = match req.decoded_datagram() {
+            Some(datagram) if datagram.len() <= MAX_RELAYED_LEN => 
+                datagram.len(),
+            _ => return self.refuse(src_addr, 
+                                    ErrorCode::MalformedRequest).await,
+        };
+
+        let target_addr = self.lookup_id(&req.target.to_string())
+            .filter(|target| target.is_online(unix_timestamp()))
+            .map(|target| target.observed_addr.unwrap_or(target.addr));
+        let target_addr = match target_addr {
+            Some(target_addr) => target_addr,
+            None => return self.refuse(src_addr, ErrorCode::UnknownPeer).await,
+        };
+
+        println!("\t\x1b[1mrelaying\x1b[0m: {}B from {} to {}", datagram_len, 
+                 req.uuid, req.target);
+        let relayed = ResponseBody::Relayed { uuid: req.uuid, 
+                                              datagram: req.datagram };
+        self.push(target_addr, &Response::new(relayed)).await;
         Ok(())
     }
+
+    /// handles a message left for a peer that is offline. It waits in the
+    /// peer's mailbox until the peer fetches it, or for `MAIL_TTL_SECS`.
+    /// Refused if the peer is online, since it can be sent the message 
+    /// directly, or if its mailbox is full. Only peers that registered
+    /// before get mail, as only they have a public key to encrypt it for.
+    ///
+    /// Deposits are only checked for freshness, like punches. A replayed 
+    /// deposit leaves a copy of the message, which the recipient drops as a
+    /// duplicate.
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_deposit(&self, src_addr: SocketAddr, req: Deposit)
+        -> Result<(), NodeError> {
+        let payload = Deposit::signing_payload(&req.uuid, &req.target, 
+                                               &req.message, req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        match req.decoded_message() {
+            Some(message) if message.len() <= MAX_DEPOSIT_LEN => (),
+            _ => return self.refuse(src_addr, 
+                                    ErrorCode::MalformedRequest).await,
+        }
+
+        let now = unix_timestamp();
+        match self.lookup_id(&req.target.to_string()) {
+            Some(target) if target.is_online(now) => 
+                return self.refuse(src_addr, ErrorCode::PeerOnline).await,
+            Some(_) => (),
+            None => return self.refuse(src_addr, ErrorCode::UnknownPeer).await,
+        }
+
+        let item = MailItem {
+            id: Uuid::new_v4(),
+            src_uuid: req.uuid,
+            message: req.message,
+            deposited_at: now,
+        };
+        let mail_id = item.id;
+        let deposited = self.mailboxes.lock().unwrap()
+            .deposit(req.target, item, now);
+        let expires_at = match deposited {
+            Ok(expires_at) => expires_at,
+            Err(code) => return self.refuse(src_addr, code).await,
+        };
+        println!("\t\x1b[1mmail deposited\x1b[0m: from {} to {}", req.uuid, 
+                 req.target);
+
+        let response = ResponseBody::Deposited { mail_id, expires_at };
+        self.respond(src_addr, &Response::new(response)).await;
+        Ok(())
+    }
+
+    /// handles a peer fetching its mail. Answers with the oldest messages,
+    /// which stay in the mailbox until the peer acknowledges them, so that
+    /// a lost response loses no mail. Must be signed by the peer, and is 
+    /// only checked for freshness since it follows the registration closely
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_fetch_mail(&self, src_addr: SocketAddr, req: FetchMail)
+        -> Result<(), NodeError> {
+        let payload = FetchMail::signing_payload(&req.uuid, req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        let (messages, remaining) = self.mailboxes.lock().unwrap()
+            .peek(&req.uuid, unix_timestamp());
+        let response = ResponseBody::Mail { messages, remaining };
+        self.respond(src_addr, &Response::new(response)).await;
+        Ok(())
+    }
+
+    /// handles a peer acknowledging fetched mail, which is removed from its
+    /// mailbox. Must be signed by the peer, and is only checked for 
+    /// freshness since removing mail twice does nothing
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_ack_mail(&self, src_addr: SocketAddr, req: AckMail)
+        -> Result<(), NodeError> {
+        let payload = AckMail::signing_payload(&req.uuid, &req.ids, 
+                                               req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        let removed = self.mailboxes.lock().unwrap()
+            .remove(&req.uuid, &req.ids);
+        println!("\t\x1b[1mmail fetched\x1b[0m: UUID = {}, {} messages", 
+                 req.uuid, removed);
+        let response = ResponseBody::MailAcked { removed };
+        self.respond(src_addr, &Response::new(response)).await;
+        Ok(())
+    }
+
+    /// handles a client leaving the network. Ends its lease so that nobody is
+    /// routed to its socket anymore. The PeerNode is kept, offline, so that
+    /// its username stays reserved until the sweeper evicts it. Must be 
+    /// signed by the identity key the peer registered with.
+    ///
+    /// `src_addr`: the requesting addr
+    /// `req`: the received request
+    pub async fn handle_deregistration(&self, src_addr: SocketAddr, 
+                                       req: Deregistration) 
+        -> Result<(), NodeError> {
+        let payload = Deregistration::signing_payload(&req.uuid, req.timestamp);
+        if let Err(code) = self.verify_owner(&req.uuid, &payload, 
+                                             &req.signature, req.timestamp) {
+            return self.refuse(src_addr, code).await;
+        }
+
+        let left = self.update_peer(&req.uuid.to_string(), |peer| {
+            accept_timestamp(peer, req.timestamp)?;
+            peer.last_timestamp = req.timestamp;
+            peer.expires_at = unix_timestamp();
+            Ok(())
+        });
+        if let Err(code) = left {
+            return self.refuse(src_addr, code).await;
+        }
+        println!("\t\x1b[1mpeer left\x1b[0m: UUID = {}", req.uuid);
+
+        self.respond(src_addr, &Response::new(ResponseBody::Deregistered))
+            .await;
+        Ok(())
+    }
+
+    /// checks that a request on behalf of `uuid` was signed by its owner 
+    /// recently, without looking at the timestamps of earlier requests
+    fn verify_owner(&self, uuid: &Uuid, payload: &str, signature: &str,
+                    timestamp: u64) -> Result<PeerNode, ErrorCode> {
+        // evicted peers have to register again
+        let peer = match self.lookup_id(&uuid.to_string()) {
+            Some(peer) => peer,
+            None => return Err(ErrorCode::UnknownPeer),
+        };
+
+        let verifying_key = parse_verifying_key(&peer.verifying_key).unwrap();
+        if !verify_signature(&verifying_key, payload, signature) {
+            return Err(ErrorCode::InvalidSignature);
+        }
+
+        if !is_fresh(timestamp) {
+            return Err(ErrorCode::StaleRequest);
+        }
+
+        Ok(peer)
+    }
+
+    /// has the signer of `watch` told when the peer `watched` moves, see
+    /// notify_watchers(). A watch that fails verification only goes
+    /// unrecorded, the lookup it comes with is answered anyway
+    ///
+    /// `looked_up`: the UUID or username the lookup named, which is signed
+    fn add_watch(&self, watch: &Watch, watched: Uuid, looked_up: &str) {
+        let payload = Watch::signing_payload(&watch.uuid, looked_up, 
+                                             watch.timestamp);
+        match self.verify_owner(&watch.uuid, &payload, &watch.signature,
+                                watch.timestamp) {
+            Ok(_) => self.watchers.lock().unwrap()
+                .watch(watched, watch.uuid, unix_timestamp()),
+            Err(code) => println!("\t\x1b[31mwatch refused\x1b[0m: {}", code),
+        }
+    }
+
+    /// tells the peers that recently looked `uuid` up where it is now, so
+    /// that they stop sending to its old address. Sent to where their 
+    /// punches come from, or to their registered address
+    ///
+    /// `peer`: the peer holding `uuid`, as just updated
+    async fn notify_watchers(&self, uuid: Uuid, peer: &PeerNode) {
+        let now = unix_timestamp();
+        let watchers = self.watchers.lock().unwrap().watchers_of(&uuid, now);
+        let moved = Response::new(ResponseBody::PeerMoved {
+            uuid,
+            address: peer.addr,
+            observed_address: peer.observed_addr,
+        });
+        for watcher in watchers {
+            let watcher = match self.lookup_id(&watcher.to_string()) {
+                Some(watcher) if watcher.is_online(now) => watcher,
+                _ => continue,
+            };
+            let watcher_addr = watcher.observed_addr.unwrap_or(watcher.addr);
+            println!("\t\x1b[1mpeer moved\x1b[0m: telling {} about {}", 
+                     watcher.id, uuid);
+            self.push(watcher_addr, &moved).await;
+        }
+    }
+
+    /// answers a request that could not be parsed, or failed authentication
+    /// or validation
+    ///
+    /// `code`: why the request was refused, sent to the client
+    async fn refuse(&self, src_addr: SocketAddr, code: ErrorCode) 
+        -> Result<(), NodeError> {
+        println!("\t\x1b[31mrequest refused\x1b[0m: {}", code);
+
+        self.respond(src_addr, &Response::error(code)).await;
+        match code {
+            ErrorCode::MalformedRequest | ErrorCode::UnsupportedVersion =>
+                Err(NodeError::JsonParseError(code.to_string())),
+            _ => Err(NodeError::AuthenticationError(code.to_string())),
+        }
+    }
+
+    /// sends a response to a client. A client that can't be reached anymore
+    /// only loses its own response, so failures are just logged
+    ///
+    /// The answer to the request being handled echoes its id, and is kept
+    /// for when the request is retransmitted
+    async fn respond(&self, dst_addr: SocketAddr, response: &Response) {
+        let requester = REQUESTER.try_with(|requester| *requester).ok();
+        let echoed;
+        let response = match requester {
+            Some(Requester { addr, id: Some(id) }) if addr == dst_addr => {
+                echoed = Response { id: Some(id), ..response.clone() };
+                self.replies.lock().unwrap()
+                    .insert(id, addr, echoed.clone(), unix_timestamp());
+                &echoed
+            },
+            _ => response,
+        };
+        self.push(dst_addr, response).await;
+    }
+
+    /// sends a datagram the client didn't ask for, e.g. a relayed one. It
+    /// never echoes an id, even if the client is the one being answered, so
+    /// the client can tell it apart from the response it waits for
+    async fn push(&self, dst_addr: SocketAddr, response: &Response) {
+        let dst_addr = match reachable_from(&self.listen_addr, dst_addr) {
+            Some(dst_addr) => dst_addr,
+            None => {
+                println!("\t\x1b[31mCan't reach {} from {}\x1b[0m", 
+                         dst_addr, self.listen_addr);
+                return;
+            }
+        };
+        if let Err(err) = self.listening_socket
+            .send_to(&response.to_bytes(), dst_addr).await {
+            println!("\t\x1b[31mError responding to {}\x1b[0m: {}", 
+                     dst_addr, err);
+        }
+    }
+}
+
+/// the answer to a lookup of `uuid`
+///
+/// `peer`: the peer holding `uuid`, if any
+fn peer_info(uuid: Uuid, peer: Option<PeerNode>) -> ResponseBody {
+    match peer {
+        Some(peer) if peer.is_online(unix_timestamp()) => 
+            ResponseBody::PeerInfo {
+                uuid,
+                status: PeerStatus::Online,
+                address: Some(peer.addr),
+                observed_address: peer.observed_addr,
+                public_key: Some(peer.public_key),
+                codecs: peer.codecs,
+            },
+        // no address, but what is needed to leave the peer some mail
+        Some(peer) => ResponseBody::PeerInfo {
+            uuid,
+            status: PeerStatus::Offline,
+            address: None,
+            observed_address: None,
+            public_key: Some(peer.public_key),
+            codecs: peer.codecs,
+        },
+        None => ResponseBody::PeerInfo {
+            uuid,
+            status: PeerStatus::Unknown,
+            address: None,
+            observed_address: None,
+            public_key: None,
+            codecs: Vec::new(),
+        },
+    }
+}
+
+/// derives the UUID owned by `verifying_key` from its SHA-256 hash
+pub fn derive_uuid(verifying_key: &VerifyingKey) -> Uuid {
+    let digest = Sha256::digest(verifying_key.as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    Builder::from_custom_bytes(bytes).into_uuid()
+}
+
+/// parses the hex representation of an ed25519 verifying key
+fn parse_verifying_key(verifying_key_hex: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(verifying_key_hex).ok()?
+        .try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+/// checks that `signature_hex` is a valid signature of `payload`
+fn verify_signature(verifying_key: &VerifyingKey, payload: &str, 
+                    signature_hex: &str) -> bool {
+    let bytes: [u8; 64] = match hex::decode(signature_hex).ok()
+        .and_then(|bytes| bytes.try_into().ok()) {
+        Some(bytes) => bytes,
+        None => return false,
+    };
+    let signature = Signature::from_bytes(&bytes);
+    verifying_key.verify_strict(payload.as_bytes(), &signature).is_ok()
+}
+
+/// refuses a request that isn't newer than the last one accepted from 
+/// `peer`, i.e. a replay. Checked under the lock the peer is updated under, 
+/// see ServerNode::update_peer()
+fn accept_timestamp(peer: &PeerNode, timestamp: u64) -> Result<(), ErrorCode> {
+    match timestamp > peer.last_timestamp {
+        true => Ok(()),
+        false => Err(ErrorCode::StaleRequest),
+    }
+}
+
+/// whether a request timestamp is within `MAX_CLOCK_SKEW_MILLIS` of now
+fn is_fresh(timestamp: u64) -> bool {
+    unix_timestamp_millis().abs_diff(timestamp) <= MAX_CLOCK_SKEW_MILLIS
+}
+
+/// restricts a requested lease to the bounds the server grants
+fn clamp_ttl(ttl: u64) -> u64 {
+    ttl.clamp(MIN_LEASE_TTL_SECS, MAX_LEASE_TTL_SECS)
+}
+
+/// seconds since the unix epoch
+fn unix_timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+/// milliseconds since the unix epoch, the unit of request timestamps
+fn unix_timestamp_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
-struct T(());
+use std::error;
+use std::fmt;
 
-/// TODO: make this implement Error class 
+/// errors relating to server activity
+#[derive(Debug, Clone)]
 pub enum NodeError {
     NodeCreationError(String),
     JsonParseError(String),
+    AuthenticationError(String),
+    StorageError(String),
+}
+
+impl error::Error for NodeError {}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::NodeCreationError(msg) => 
+                write!(f, "NodeCreationError: {}", msg),
+            NodeError::JsonParseError(msg) => 
+                write!(f, "JsonParseError: {}", msg),
+            NodeError::AuthenticationError(msg) => 
+                write!(f, "AuthenticationError: {}", msg),
+            NodeError::StorageError(msg) => 
+                write!(f, "StorageError: {}", msg),
+        }
+    }
 }
 
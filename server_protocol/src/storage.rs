@@ -0,0 +1,471 @@
+/*
+ * File: storage.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: storage backends for the server's peer index
+ */
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use json::JsonValue;
+use protocol::codec::{self, Codec};
+use crate::server::{NodeError, PeerNode};
+
+/// the log is compacted once it holds this many superseded records, and more
+/// superseded records than live ones
+static COMPACTION_THRESHOLD: usize = 1024;
+
+/// where the server keeps its `PeerNode`s. Shared between the tasks handling
+/// requests, hence `Send + Sync`
+pub trait PeerStore: Send + Sync {
+    /// inserts a peer, replacing any peer with the same id
+    fn insert(&mut self, peer: PeerNode) -> Result<(), NodeError>;
+
+    /// removes a peer, returning it if it was present
+    fn remove(&mut self, id: &str) -> Result<Option<PeerNode>, NodeError>;
+
+    /// looks a peer up based on ID
+    fn get(&self, id: &str) -> Option<PeerNode>;
+
+    /// looks a peer up based on the username it registered with
+    fn get_by_username(&self, username: &str) -> Option<PeerNode>;
+
+    /// removes every peer for which `keep` returns false. Returns the ids of
+    /// the removed peers
+    fn retain(&mut self, keep: &dyn Fn(&PeerNode) -> bool)
+        -> Result<Vec<String>, NodeError>;
+
+    /// a handle on what was written since the last call but may not have
+    /// reached the disk yet, for the caller to flush without holding the
+    /// store. `None` if there is nothing to flush
+    fn unsynced_log(&mut self) -> Result<Option<File>, NodeError> {
+        Ok(None)
+    }
+}
+
+/// the peers kept in RAM by both stores, along with a username index
+#[derive(Default)]
+struct PeerIndex {
+    peers: HashMap<String, PeerNode>,
+    usernames: HashMap<String, String>,     // username -> id
+}
+
+impl PeerIndex {
+    /// inserts a peer, returning the one it replaced
+    fn insert(&mut self, peer: PeerNode) -> Option<PeerNode> {
+        let replaced = self.remove(&peer.id);
+        if let Some(username) = &peer.username {
+            self.usernames.insert(username.clone(), peer.id.clone());
+        }
+        self.peers.insert(peer.id.clone(), peer);
+        replaced
+    }
+
+    fn remove(&mut self, id: &str) -> Option<PeerNode> {
+        let peer = self.peers.remove(id)?;
+        if let Some(username) = &peer.username {
+            if self.usernames.get(username).map(String::as_str) == Some(id) {
+                self.usernames.remove(username);
+            }
+        }
+        Some(peer)
+    }
+
+    fn get(&self, id: &str) -> Option<PeerNode> {
+        self.peers.get(id).cloned()
+    }
+
+    fn get_by_username(&self, username: &str) -> Option<PeerNode> {
+        self.get(self.usernames.get(username)?)
+    }
+
+    /// ids of the peers for which `keep` returns false
+    fn rejected(&self, keep: &dyn Fn(&PeerNode) -> bool) -> Vec<String> {
+        self.peers.values()
+            .filter(|peer| !keep(peer))
+            .map(|peer| peer.id.clone())
+            .collect()
+    }
+}
+
+/// keeps the index in RAM only. Everything is lost when the server exits
+#[derive(Default)]
+pub struct MemoryStore {
+    index: PeerIndex,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl PeerStore for MemoryStore {
+    fn insert(&mut self, peer: PeerNode) -> Result<(), NodeError> {
+        self.index.insert(peer);
+        Ok(())
+    }
+
+    fn remove(&mut self, id: &str) -> Result<Option<PeerNode>, NodeError> {
+        Ok(self.index.remove(id))
+    }
+
+    fn get(&self, id: &str) -> Option<PeerNode> {
+        self.index.get(id)
+    }
+
+    fn get_by_username(&self, username: &str) -> Option<PeerNode> {
+        self.index.get_by_username(username)
+    }
+
+    fn retain(&mut self, keep: &dyn Fn(&PeerNode) -> bool)
+        -> Result<Vec<String>, NodeError> {
+        let removed = self.index.rejected(keep);
+        for id in &removed {
+            self.index.remove(id);
+        }
+        Ok(removed)
+    }
+}
+
+/// keeps the index in RAM and every change in an append-only log on disk,
+/// one json record per line. The log is replayed when the server starts, so
+/// the index survives restarts. Records are flushed to disk in batches, by 
+/// whoever calls `unsynced_log()`.
+///
+/// ```
+/// {"op": "put", "peer": { <PeerNode fields> }}
+/// {"op": "del", "id": "<uuid>"}
+/// ```
+pub struct FileStore {
+    path: PathBuf,
+    log: File,
+    index: PeerIndex,
+    superseded_records: usize,
+    unsynced: bool,     // records were appended since the last flush
+}
+
+impl FileStore {
+    /// opens the log at `path`, creating it if needed, and replays it
+    pub fn open(path: &Path) -> Result<FileStore, NodeError> {
+        let mut index = PeerIndex::default();
+
+        if path.exists() {
+            let file = File::open(path).map_err(storage_error)?;
+            let mut lines = BufReader::new(file).lines().enumerate()
+                .peekable();
+            while let Some((line_no, line)) = lines.next() {
+                let line = line.map_err(storage_error)?;
+                if replay(&mut index, &line) {
+                    continue;
+                }
+                // a crash mid-write leaves a torn last record behind, skip 
+                // it. Anywhere else, the log is corrupt
+                if lines.peek().is_some() {
+                    let err_msg = format!("Corrupt record on line {} of {}",
+                                          line_no + 1, path.display());
+                    return Err(NodeError::StorageError(err_msg));
+                }
+                println!("Skipping torn last record of {}", path.display());
+            }
+        }
+
+        let log = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(storage_error)?;
+        let mut store = FileStore {
+            path: path.to_path_buf(),
+            log,
+            index,
+            superseded_records: 0,
+            unsynced: false,
+        };
+
+        // start from a log holding only the live records
+        store.compact()?;
+        Ok(store)
+    }
+
+    /// appends a record to the log. It reaches the disk with the next 
+    /// flush, see `unsynced_log()`
+    fn append(&mut self, record: JsonValue) -> Result<(), NodeError> {
+        let mut line = record.dump();
+        line.push('\n');
+        self.log.write_all(line.as_bytes()).map_err(storage_error)?;
+        self.unsynced = true;
+        Ok(())
+    }
+
+    /// rewrites the log with one `put` record per live peer. The new log is
+    /// written next to the old one and renamed over it, so a crash during
+    /// compaction leaves either the old or the new log intact.
+    fn compact(&mut self) -> Result<(), NodeError> {
+        let tmp_path = self.path.with_extension("compact");
+        let mut tmp = File::create(&tmp_path).map_err(storage_error)?;
+        for peer in self.index.peers.values() {
+            let mut line = put_record(peer).dump();
+            line.push('\n');
+            tmp.write_all(line.as_bytes()).map_err(storage_error)?;
+        }
+        tmp.sync_all().map_err(storage_error)?;
+        fs::rename(&tmp_path, &self.path).map_err(storage_error)?;
+
+        self.log = OpenOptions::new().append(true).open(&self.path)
+            .map_err(storage_error)?;
+        self.superseded_records = 0;
+        self.unsynced = false;
+        Ok(())
+    }
+
+    /// compacts the log once superseded records dominate it
+    fn maybe_compact(&mut self) -> Result<(), NodeError> {
+        if self.superseded_records > COMPACTION_THRESHOLD
+            && self.superseded_records > self.index.peers.len() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+impl PeerStore for FileStore {
+    fn insert(&mut self, peer: PeerNode) -> Result<(), NodeError> {
+        self.append(put_record(&peer))?;
+        if self.index.insert(peer).is_some() {
+            self.superseded_records += 1;
+        }
+        self.maybe_compact()
+    }
+
+    fn remove(&mut self, id: &str) -> Result<Option<PeerNode>, NodeError> {
+        if !self.index.peers.contains_key(id) {
+            return Ok(None);
+        }
+        self.append(del_record(id))?;
+        // both the put and the del record are now superseded
+        self.superseded_records += 2;
+        let removed = self.index.remove(id);
+        self.maybe_compact()?;
+        Ok(removed)
+    }
+
+    fn get(&self, id: &str) -> Option<PeerNode> {
+        self.index.get(id)
+    }
+
+    fn get_by_username(&self, username: &str) -> Option<PeerNode> {
+        self.index.get_by_username(username)
+    }
+
+    fn retain(&mut self, keep: &dyn Fn(&PeerNode) -> bool)
+        -> Result<Vec<String>, NodeError> {
+        let removed = self.index.rejected(keep);
+        for id in &removed {
+            self.remove(id)?;
+        }
+        Ok(removed)
+    }
+
+    fn unsynced_log(&mut self) -> Result<Option<File>, NodeError> {
+        if !self.unsynced {
+            return Ok(None);
+        }
+        let log = self.log.try_clone().map_err(storage_error)?;
+        self.unsynced = false;
+        Ok(Some(log))
+    }
+}
+
+/// applies a log record to `index`. False if the record can't be parsed
+fn replay(index: &mut PeerIndex, line: &str) -> bool {
+    let record = match json::parse(line) {
+        Ok(record) => record,
+        Err(_) => return false,
+    };
+    if record["op"] == "put" {
+        match peer_from_json(&record["peer"]) {
+            Some(peer) => index.insert(peer),
+            None => return false,
+        };
+    } else if record["op"] == "del" {
+        match record["id"].as_str() {
+            Some(id) => index.remove(id),
+            None => return false,
+        };
+    } else {
+        return false;
+    }
+    true
+}
+
+/// a log record storing `peer`
+fn put_record(peer: &PeerNode) -> JsonValue {
+    let mut record = JsonValue::new_object();
+    record["op"] = JsonValue::from("put");
+    record["peer"] = peer_to_json(peer);
+    record
+}
+
+/// a log record removing the peer `id`
+fn del_record(id: &str) -> JsonValue {
+    let mut record = JsonValue::new_object();
+    record["op"] = JsonValue::from("del");
+    record["id"] = JsonValue::from(id);
+    record
+}
+
+fn peer_to_json(peer: &PeerNode) -> JsonValue {
+    let mut json_val = JsonValue::new_object();
+    json_val["id"] = JsonValue::from(peer.id.clone());
+    json_val["addr"] = JsonValue::from(peer.addr.to_string());
+    json_val["public_key"] = JsonValue::from(peer.public_key.clone());
+    json_val["verifying_key"] = JsonValue::from(peer.verifying_key.clone());
+    json_val["last_timestamp"] = JsonValue::from(peer.last_timestamp);
+    json_val["expires_at"] = JsonValue::from(peer.expires_at);
+    json_val["codecs"] = peer.codecs.iter()
+        .map(|codec| codec.as_str())
+        .collect::<Vec<&str>>()
+        .into();
+    json_val["username"] = match &peer.username {
+        Some(username) => JsonValue::from(username.clone()),
+        None => JsonValue::Null,
+    };
+    json_val["observed_addr"] = match &peer.observed_addr {
+        Some(observed_addr) => JsonValue::from(observed_addr.to_string()),
+        None => JsonValue::Null,
+    };
+    json_val
+}
+
+fn peer_from_json(json_val: &JsonValue) -> Option<PeerNode> {
+    Some(PeerNode {
+        id: json_val["id"].as_str()?.to_string(),
+        addr: json_val["addr"].as_str()?.parse::<SocketAddr>().ok()?,
+        public_key: json_val["public_key"].as_str()?.to_string(),
+        verifying_key: json_val["verifying_key"].as_str()?.to_string(),
+        last_timestamp: json_val["last_timestamp"].as_u64()?,
+        expires_at: json_val["expires_at"].as_u64()?,
+        // logs written before codecs were advertised only hold json peers
+        codecs: if json_val["codecs"].is_array() {
+            json_val["codecs"].members()
+                .filter_map(|codec| Codec::parse(codec.as_str()?))
+                .collect()
+        } else {
+            codec::default_codecs()
+        },
+        username: json_val["username"].as_str().map(str::to_string),
+        observed_addr: json_val["observed_addr"].as_str()
+            .and_then(|addr| addr.parse().ok()),
+    })
+}
+
+fn storage_error(err: std::io::Error) -> NodeError {
+    NodeError::StorageError(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    /// a log file of its own for every test
+    fn log_path() -> PathBuf {
+        std::env::temp_dir().join(format!("p2p_store_{}.log", Uuid::new_v4()))
+    }
+
+    fn peer(id: &str) -> PeerNode {
+        PeerNode {
+            id: id.to_string(),
+            addr: "127.0.0.1:6001".parse().unwrap(),
+            public_key: "aa".repeat(32),
+            verifying_key: "bb".repeat(32),
+            last_timestamp: 1,
+            expires_at: 2,
+            codecs: codec::default_codecs(),
+            username: Some(format!("user_{}", id)),
+            observed_addr: None,
+        }
+    }
+
+    #[test]
+    fn puts_and_dels_survive_a_restart() {
+        let path = log_path();
+        let mut store = FileStore::open(&path).unwrap();
+        store.insert(peer("a")).unwrap();
+        store.insert(peer("b")).unwrap();
+        let mut moved = peer("a");
+        moved.addr = "127.0.0.1:6002".parse().unwrap();
+        store.insert(moved.clone()).unwrap();
+        store.remove("b").unwrap();
+        drop(store);
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get("a").map(|peer| peer.addr), Some(moved.addr));
+        assert!(store.get("b").is_none());
+        assert!(store.get_by_username("user_b").is_none());
+        assert_eq!(store.get_by_username("user_a").map(|peer| peer.id),
+                   Some("a".to_string()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_appended_records_need_a_flush() {
+        let path = log_path();
+        let mut store = FileStore::open(&path).unwrap();
+        assert!(store.unsynced_log().unwrap().is_none());
+
+        store.insert(peer("a")).unwrap();
+        store.unsynced_log().unwrap().unwrap().sync_data().unwrap();
+        assert!(store.unsynced_log().unwrap().is_none());
+        assert!(MemoryStore::new().unsynced_log().unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn opening_compacts_the_log() {
+        let path = log_path();
+        let mut store = FileStore::open(&path).unwrap();
+        store.insert(peer("a")).unwrap();
+        store.insert(peer("a")).unwrap();
+        store.insert(peer("b")).unwrap();
+        store.remove("b").unwrap();
+        drop(store);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+
+        FileStore::open(&path).unwrap();
+        let log = fs::read_to_string(&path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains(r#""op":"put""#));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_torn_last_record_is_skipped() {
+        let path = log_path();
+        let mut store = FileStore::open(&path).unwrap();
+        store.insert(peer("a")).unwrap();
+        drop(store);
+        let mut log = OpenOptions::new().append(true).open(&path).unwrap();
+        log.write_all(br#"{"op": "put", "peer": {"id": "#).unwrap();
+        drop(log);
+
+        let store = FileStore::open(&path).unwrap();
+        assert!(store.get("a").is_some());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn a_corrupt_record_mid_log_is_refused() {
+        let path = log_path();
+        let mut log = put_record(&peer("a")).dump();
+        log.push_str("\n{\"op\": \"put\", \"peer\": {}}\n");
+        log.push_str(&del_record("a").dump());
+        log.push('\n');
+        fs::write(&path, log).unwrap();
+
+        assert!(FileStore::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
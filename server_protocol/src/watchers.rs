@@ -0,0 +1,105 @@
+/*
+ * File: watchers.rs
+ * Author: Ethan Graham
+ * Date: 16 Oct. 2026
+ *
+ * Description: peers that recently looked others up, and are told when the
+ * address they were given changes
+ */
+
+use std::collections::HashMap;
+use protocol::PEER_CACHE_TTL_SECS;
+use uuid::Uuid;
+
+/// for every watched peer, the peers watching it and until when. Kept in RAM
+/// only, watches last no longer than the lookups they come with are cached
+#[derive(Default)]
+pub struct Watchers {
+    watchers: HashMap<Uuid, HashMap<Uuid, u64>>,
+}
+
+impl Watchers {
+    pub fn new() -> Watchers {
+        Watchers::default()
+    }
+
+    /// has `watcher` told about changes to `watched` for the next 
+    /// `PEER_CACHE_TTL_SECS`. Watching again extends the watch
+    ///
+    /// `now`: unix time
+    pub fn watch(&mut self, watched: Uuid, watcher: Uuid, now: u64) {
+        self.watchers.entry(watched).or_default()
+            .insert(watcher, now + PEER_CACHE_TTL_SECS);
+    }
+
+    /// the peers still watching `watched` at unix time `now`
+    pub fn watchers_of(&self, watched: &Uuid, now: u64) -> Vec<Uuid> {
+        match self.watchers.get(watched) {
+            Some(watchers) => watchers.iter()
+                .filter(|(_, until)| now < **until)
+                .map(|(watcher, _)| *watcher)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// drops every watch that ended by unix time `now`, returning how many
+    /// there were
+    pub fn purge(&mut self, now: u64) -> usize {
+        let mut purged = 0;
+        self.watchers.retain(|_, watchers| {
+            let before = watchers.len();
+            watchers.retain(|_, until| now < *until);
+            purged += before - watchers.len();
+            !watchers.is_empty()
+        });
+        purged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static NOW: u64 = 1_000_000;
+
+    #[test]
+    fn watches_end_with_the_cached_lookup() {
+        let mut watchers = Watchers::new();
+        let (watched, watcher) = (Uuid::new_v4(), Uuid::new_v4());
+        watchers.watch(watched, watcher, NOW);
+
+        assert_eq!(watchers.watchers_of(&watched, NOW), vec![watcher]);
+        assert!(watchers.watchers_of(&watcher, NOW).is_empty());
+        let end = NOW + PEER_CACHE_TTL_SECS;
+        assert_eq!(watchers.watchers_of(&watched, end - 1), vec![watcher]);
+        assert!(watchers.watchers_of(&watched, end).is_empty());
+    }
+
+    #[test]
+    fn watching_again_extends_the_watch() {
+        let mut watchers = Watchers::new();
+        let (watched, watcher) = (Uuid::new_v4(), Uuid::new_v4());
+        watchers.watch(watched, watcher, NOW);
+        watchers.watch(watched, watcher, NOW + 10);
+
+        let end = NOW + 10 + PEER_CACHE_TTL_SECS;
+        assert_eq!(watchers.watchers_of(&watched, end - 1), vec![watcher]);
+        assert_eq!(watchers.purge(end - 1), 0);
+        assert_eq!(watchers.purge(end), 1);
+        assert!(watchers.watchers.is_empty());
+    }
+
+    #[test]
+    fn purge_only_drops_ended_watches() {
+        let mut watchers = Watchers::new();
+        let watched = Uuid::new_v4();
+        let (early, late) = (Uuid::new_v4(), Uuid::new_v4());
+        watchers.watch(watched, early, NOW);
+        watchers.watch(watched, late, NOW + 60);
+
+        assert_eq!(watchers.purge(NOW + PEER_CACHE_TTL_SECS), 1);
+        assert_eq!(watchers.watchers_of(&watched, NOW + PEER_CACHE_TTL_SECS),
+                   vec![late]);
+    }
+}